@@ -1,10 +1,29 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt, fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
 use ratatui::{
+    layout::{Position, Rect},
     style::{palette::tailwind, Color},
     widgets::{ScrollbarState, TableState},
 };
 use unicode_width::UnicodeWidthStr;
 
-use crate::reference::Reference;
+use crate::{
+    diff::{self, DiffLine},
+    editor::{FieldEditor, TextInput},
+    history::{Change, History, Step},
+    parse::{parse_bibtex, Bibliography, Block, ParseError},
+    reference::Reference,
+    search::{self, Query, QueryError, SearchIndex},
+    sort::{self, SortColumn, SortKeys, SortOrder},
+    write::{save_bibtex, write_bibtex, SourceFile},
+    yank::{self, YankFormat},
+};
 
 const PALETTES: [tailwind::Palette; 4] = [
     tailwind::BLUE,
@@ -15,6 +34,65 @@ const PALETTES: [tailwind::Palette; 4] = [
 
 pub const ITEM_HEIGHT: usize = 1;
 
+/// What happened to the entries of a BibTeX text that were added.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AddedReferences {
+    /// The keys of the references that were added
+    pub keys: Vec<String>,
+    /// The (original, new) keys of the references that were added with a different key, because theirs was taken
+    pub renamed: Vec<(String, String)>,
+    /// The keys of the entries that were left out, with the keys of the references they duplicate
+    pub duplicates: Vec<(String, String)>,
+}
+
+/// BibTeX entries that are being pasted or typed, and why they could not be added, if they couldn't.
+#[derive(Debug, Clone)]
+pub struct PasteBuffer {
+    pub input: TextInput,
+    pub error: Option<String>,
+}
+
+/// The BibTeX of a reference as it was edited in an external editor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalEdit {
+    /// The index in items of the edited reference
+    pub index: usize,
+    pub bibtex: String,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    /// The references were not read from a file
+    NoFile,
+    /// The file was changed by another program since it was read
+    ChangedOnDisk,
+    Io(io::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::NoFile => write!(f, "There is no file to save to."),
+            SaveError::ChangedOnDisk => write!(f, "The file was changed since it was read."),
+            SaveError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(error: io::Error) -> Self {
+        SaveError::Io(error)
+    }
+}
+
+/// Files with more references than this are only searched once typing pauses for `SEARCH_DELAY`
+const LARGE_BIBLIOGRAPHY: usize = 2000;
+const SEARCH_DELAY: Duration = Duration::from_millis(150);
+/// The number of searches that are remembered
+const SEARCH_HISTORY_LENGTH: usize = 100;
+/// The number of unchanged lines shown around every change to the file
+const DIFF_CONTEXT: usize = 3;
+
 pub struct TableColors {
     pub buffer_bg: Color,
     pub header_bg: Color,
@@ -24,7 +102,6 @@ pub struct TableColors {
     pub search_result_fg: Color,
     pub normal_row_color: Color,
     pub alt_row_color: Color,
-    pub footer_border_color: Color,
 }
 
 impl TableColors {
@@ -38,7 +115,6 @@ impl TableColors {
             search_result_fg: color.c600,
             normal_row_color: tailwind::SLATE.c950,
             alt_row_color: tailwind::SLATE.c900,
-            footer_border_color: color.c400,
         }
     }
 }
@@ -61,33 +137,152 @@ pub enum StatusBar {
 
 pub struct App {
     pub state: TableState,
+    // The references in the order of the file, which is also the order in which they were added
     pub items: Vec<Reference>,
-    pub longest_item_lens: (u16, u16, u16, u16), // order is (key, author, year, title)
+    // The indices in items of the references shown in the table, in the order in which they are shown
+    pub view: Vec<usize>,
+    // Whether the table only shows the matches of the search, and which reference was selected before
+    pub filtered: bool,
+    pub selection_before_filter: Option<usize>,
+    // How the table is sorted, and the values of the references in items that it is sorted by
+    pub sort_order: SortOrder,
+    pub sort_keys: Vec<SortKeys>,
+    // The position and width of the columns of the header of the table when it was last drawn, so
+    // that clicking on one sorts by it
+    pub header_columns: Vec<Rect>,
+    pub longest_item_lens: (u16, u16, u16, u16, u16), // order is (key, entry type, author, year, title)
     pub scroll_state: ScrollbarState,
     pub colors: TableColors,
     pub color_index: usize,
     pub status_bar: StatusBar,
-    pub search_results: Vec<Reference>,
+    // The normalized texts of the references in items, to search them quickly, and whether words are
+    // reduced to their stems when searching
+    pub search_index: SearchIndex,
+    pub stem: bool,
+    // The indices in items of the matches of the last search, best first, and for each match the
+    // indices of the matched characters in each column of the table
+    pub search_results: Vec<usize>,
+    pub search_hits: HashMap<usize, [Vec<usize>; 5]>,
+    // The last search, used to highlight matches
+    pub search_query: Option<Query>,
+    // The position in search_results of the match that was last jumped to
+    pub current_match: usize,
+    // When to search for the query that is being typed, and which reference was selected before, to go
+    // back to it if the search is cancelled
+    pub search_deadline: Option<Instant>,
+    pub selection_before_search: Option<usize>,
+    // Previous searches, oldest first, and the position in it while browsing it with Up and Down
+    pub search_history: Vec<String>,
+    pub history_position: Option<usize>,
+    // Whether to show the detail pane with all fields of the selected reference, and how far it is scrolled
+    pub show_details: bool,
+    pub details_scroll: u16,
+    // The form for editing the selected reference, while it is open
+    pub editor: Option<FieldEditor>,
+    // An entry that was edited in an external editor, but could not be used, so that it can be edited again
+    pub external_edit: Option<ExternalEdit>,
+    // The text box for pasting or typing BibTeX entries to add, while it is open
+    pub paste_buffer: Option<PasteBuffer>,
+    // The changes to the references that can be undone and redone
+    pub history: History,
+    // The indices in items of the marked references, which commands act on instead of the selected one, and
+    // the reference where the range that is being marked in visual mode starts
+    pub marked: BTreeSet<usize>,
+    pub visual_anchor: Option<usize>,
+    // The formats that references can be copied to the clipboard in
+    pub yank_formats: Vec<YankFormat>,
+    // The first key of a command of two keys, like dd, while waiting for the second
+    pub pending_key: Option<char>,
+    // The file the references were read from, which changes are written back to
+    pub file: Option<SourceFile>,
+    // The directory where the file is backed up before it is overwritten, and how many backups are kept
+    pub backup_directory: Option<PathBuf>,
+    pub backup_count: usize,
+    // What the file contains if it was changed by another program since it was read, so that saving has
+    // to be confirmed, and how it was changed (the changed lines with a few lines around them), which is
+    // found once since it can take a while for large files, and whether to show that
+    pub conflict: Option<String>,
+    pub conflict_diff: Vec<Option<DiffLine<String>>>,
+    pub show_conflict_diff: bool,
+    pub conflict_diff_scroll: u16,
+    // Entries in the file that could not be parsed
+    pub errors: Vec<ParseError>,
+    pub show_errors: bool,
+    pub errors_scroll: u16,
 }
 
 impl App {
-    pub fn new(references: Vec<Reference>) -> App {
+    pub fn new(references: Vec<Reference>, errors: Vec<ParseError>) -> App {
+        let status_message = match errors.len() {
+            0 => String::default(),
+            1 => String::from("1 entry could not be parsed. Press ! to show the error."),
+            n => format!(
+                "{} entries could not be parsed. Press ! to show the errors.",
+                n
+            ),
+        };
+        let sort_keys: Vec<SortKeys> = references.iter().map(SortKeys::new).collect();
+        let sort_order = SortOrder::default();
+        let mut view: Vec<usize> = (0..references.len()).collect();
+        view.sort_by(|&a, &b| sort::compare(&sort_keys, sort_order, a, b));
+
         App {
             state: TableState::default().with_selected(0),
             longest_item_lens: constraint_len_calculator(&references),
-            scroll_state: ScrollbarState::new((references.len() - 1) * ITEM_HEIGHT),
+            scroll_state: ScrollbarState::new(references.len().saturating_sub(1) * ITEM_HEIGHT),
             colors: TableColors::new(&PALETTES[0]),
             color_index: 0,
+            view,
+            search_index: SearchIndex::new(&references, false),
+            stem: false,
+            filtered: false,
+            selection_before_filter: None,
+            sort_order,
+            sort_keys,
+            header_columns: Vec::new(),
             items: references,
-            status_bar: StatusBar::Message(String::default()),
+            status_bar: StatusBar::Message(status_message),
             search_results: Vec::new(),
+            search_hits: HashMap::new(),
+            search_query: None,
+            current_match: 0,
+            search_deadline: None,
+            selection_before_search: None,
+            search_history: Vec::new(),
+            history_position: None,
+            show_details: false,
+            details_scroll: 0,
+            editor: None,
+            external_edit: None,
+            paste_buffer: None,
+            history: History::default(),
+            marked: BTreeSet::new(),
+            visual_anchor: None,
+            yank_formats: yank::default_formats(),
+            pending_key: None,
+            file: None,
+            backup_directory: None,
+            backup_count: 0,
+            conflict: None,
+            conflict_diff: Vec::new(),
+            show_conflict_diff: false,
+            conflict_diff_scroll: 0,
+            errors,
+            show_errors: false,
+            errors_scroll: 0,
         }
     }
 
+    /// Turns stemming of search terms on or off, which requires normalizing all references again
+    pub fn set_stemming(&mut self, stem: bool) {
+        self.stem = stem;
+        self.search_index = SearchIndex::new(&self.items, stem);
+    }
+
     pub fn select_next(&mut self) {
         let i = match self.state.selected() {
             Some(i) => {
-                if i >= self.items.len() - 1 {
+                if i >= self.view.len().saturating_sub(1) {
                     0
                 } else {
                     i + 1
@@ -95,23 +290,172 @@ impl App {
             }
             None => 0,
         };
-        self.state.select(Some(i));
-        self.scroll_state = self.scroll_state.position(i * ITEM_HEIGHT);
+        self.select(i);
     }
 
     pub fn select_previous(&mut self) {
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.items.len() - 1
+                    self.view.len().saturating_sub(1)
                 } else {
                     i - 1
                 }
             }
             None => 0,
         };
+        self.select(i);
+    }
+
+    fn select(&mut self, i: usize) {
         self.state.select(Some(i));
         self.scroll_state = self.scroll_state.position(i * ITEM_HEIGHT);
+        self.details_scroll = 0;
+    }
+
+    pub fn select_best_match(&mut self) {
+        if !self.search_results.is_empty() {
+            self.select_match(0);
+        }
+    }
+
+    /// Selects the next best match of the last search, going back to the best one after the last
+    pub fn select_next_match(&mut self) {
+        if !self.search_results.is_empty() {
+            self.select_match((self.current_match + 1) % self.search_results.len());
+        }
+    }
+
+    pub fn select_previous_match(&mut self) {
+        let count = self.search_results.len();
+        if count > 0 {
+            self.select_match((self.current_match + count - 1) % count);
+        }
+    }
+
+    /// Selects the next match below the selection in the table, wrapping around at the end, like `n` in vim
+    pub fn jump_to_next_match(&mut self) {
+        self.jump_to_match(true);
+    }
+
+    pub fn jump_to_previous_match(&mut self) {
+        self.jump_to_match(false);
+    }
+
+    fn jump_to_match(&mut self, forward: bool) {
+        let count = self.view.len();
+        let start = self.state.selected().unwrap_or(0);
+        for offset in 1..=count {
+            let i = match forward {
+                true => (start + offset) % count,
+                false => (start + count - offset) % count,
+            };
+            if self.search_hits.contains_key(&self.view[i]) {
+                self.select(i);
+                return;
+            }
+        }
+    }
+
+    fn select_match(&mut self, n: usize) {
+        self.current_match = n;
+        self.select_item(self.search_results[n]);
+    }
+
+    /// Shows only the matches of the last search in the table, or all references if nothing matched
+    pub fn filter(&mut self) {
+        if self.search_results.is_empty() {
+            self.clear_filter();
+            return;
+        }
+        if !self.filtered {
+            self.selection_before_filter = self.selected_index();
+            self.filtered = true;
+        }
+
+        let matches = self.sorted(self.search_results.clone());
+        self.set_view(matches);
+        self.select_match(0);
+    }
+
+    /// Shows all references again, selecting the one that was selected before filtering
+    pub fn clear_filter(&mut self) {
+        if !self.filtered {
+            return;
+        }
+        self.filtered = false;
+        let all = self.sorted((0..self.items.len()).collect());
+        self.set_view(all);
+        let selection = self.selection_before_filter.take().unwrap_or(0);
+        self.select_item(selection);
+    }
+
+    /// Selects the reference with the given index in items, if it is shown
+    fn select_item(&mut self, index: usize) {
+        if let Some(i) = self.view.iter().position(|&shown| shown == index) {
+            self.select(i);
+        }
+    }
+
+    /// Sorts by a column, or reverses the order if the table is already sorted by it
+    pub fn sort_by(&mut self, column: SortColumn) {
+        self.sort_order = match self.sort_order.column == column {
+            true => SortOrder {
+                column,
+                ascending: !self.sort_order.ascending,
+            },
+            false => SortOrder {
+                column,
+                ascending: true,
+            },
+        };
+        self.sort_view();
+    }
+
+    /// Sorts by the next of the sort orders, ascending
+    pub fn sort_by_next_column(&mut self) {
+        self.sort_by(self.sort_order.column.next());
+    }
+
+    pub fn reverse_sort_order(&mut self) {
+        self.sort_order.ascending = !self.sort_order.ascending;
+        self.sort_view();
+    }
+
+    /// Sorts the table according to the sort order, keeping the selected reference selected
+    fn sort_view(&mut self) {
+        let selection = self.selected_index();
+        let view = std::mem::take(&mut self.view);
+        self.view = self.sorted(view);
+        if let Some(selection) = selection {
+            self.select_item(selection);
+        }
+    }
+
+    fn sorted(&self, mut indices: Vec<usize>) -> Vec<usize> {
+        indices.sort_by(|&a, &b| sort::compare(&self.sort_keys, self.sort_order, a, b));
+        indices
+    }
+
+    /// Sorts by the column whose header is at the given position on the screen, if there is one.
+    /// Returns whether there was.
+    pub fn click_header(&mut self, x: u16, y: u16) -> bool {
+        let column = self
+            .header_columns
+            .iter()
+            .position(|area| area.contains(Position { x, y }))
+            .and_then(SortColumn::from_column);
+        if let Some(column) = column {
+            self.sort_by(column);
+        }
+        column.is_some()
+    }
+
+    fn set_view(&mut self, view: Vec<usize>) {
+        self.scroll_state = self
+            .scroll_state
+            .content_length(view.len().saturating_sub(1) * ITEM_HEIGHT);
+        self.view = view;
     }
 
     pub fn next_color(&mut self) {
@@ -127,50 +471,1010 @@ impl App {
         self.colors = TableColors::new(&PALETTES[self.color_index])
     }
 
-    pub fn yank(&self) -> Option<&Reference> {
-        let currently_selected_index = self.state.selected()?;
-        let currently_selected_reference: &Reference = self.items.get(currently_selected_index)?;
-        let reference_bibtex = currently_selected_reference.to_bibtex();
-        if let Ok(_) = cli_clipboard::set_contents(reference_bibtex) {
-            Some(currently_selected_reference)
-        } else {
-            None
+    pub fn toggle_details(&mut self) {
+        self.show_details = !self.show_details;
+        self.details_scroll = 0;
+    }
+
+    pub fn scroll_details_down(&mut self) {
+        self.details_scroll = self.details_scroll.saturating_add(1);
+    }
+
+    pub fn scroll_details_up(&mut self) {
+        self.details_scroll = self.details_scroll.saturating_sub(1);
+    }
+
+    pub fn toggle_errors(&mut self) {
+        self.show_errors = !self.show_errors;
+        self.errors_scroll = 0;
+    }
+
+    pub fn scroll_errors_down(&mut self) {
+        self.errors_scroll = self.errors_scroll.saturating_add(1);
+    }
+
+    pub fn scroll_errors_up(&mut self) {
+        self.errors_scroll = self.errors_scroll.saturating_sub(1);
+    }
+
+    /// The index in items of the selected reference
+    pub fn selected_index(&self) -> Option<usize> {
+        self.view.get(self.state.selected()?).copied()
+    }
+
+    pub fn selected_reference(&self) -> Option<&Reference> {
+        self.items.get(self.selected_index()?)
+    }
+
+    /// Copies the marked references, or else the selected one, to the clipboard in the format with the given
+    /// key. Returns the format and the keys of the references, or why they could not be copied.
+    pub fn yank(&mut self, format_key: char) -> Result<(&YankFormat, Vec<String>), String> {
+        let targets = self.targets();
+        let format = self
+            .yank_formats
+            .iter()
+            .find(|format| format.key == format_key)
+            .ok_or_else(|| format!("There is no format for {}.", format_key))?;
+        let references: Vec<&Reference> = targets.iter().map(|&index| &self.items[index]).collect();
+        if references.is_empty() {
+            return Err(String::from("There is nothing to copy."));
         }
+        let text = format.apply(&references)?;
+        cli_clipboard::set_contents(text)
+            .map_err(|_| String::from("Could not copy to the clipboard."))?;
+        let keys = references
+            .iter()
+            .map(|reference| reference.key.clone())
+            .collect();
+        Ok((format, keys))
     }
 
-    // TODO write tests for this function
-    pub fn search(&mut self) {
-        fn reference_contains(reference: &Reference, pattern: &str) -> bool {
-            let title_contains_search_string: bool = match reference.title() {
-                Some(title) => title.to_lowercase().contains(&pattern.to_lowercase()),
-                None => false,
-            };
-            let fields_contain_search_string: bool = reference
-                .fields
+    /// The indices in items of the references that a command acts on: the marked ones (including the range
+    /// that is being marked), in the order of the library, or else the selected one. Visual mode ends.
+    pub fn targets(&mut self) -> Vec<usize> {
+        let marked = self.marked_with_visual_range();
+        self.visual_anchor = None;
+        match marked.is_empty() {
+            true => self.selected_index().into_iter().collect(),
+            false => marked.into_iter().collect(),
+        }
+    }
+
+    /// The marked references, and the references between the start of visual mode and the selection
+    pub fn marked_with_visual_range(&self) -> BTreeSet<usize> {
+        let mut marked = self.marked.clone();
+        let anchor_row = self
+            .visual_anchor
+            .and_then(|anchor| self.view.iter().position(|&shown| shown == anchor));
+        if let (Some(anchor_row), Some(row)) = (anchor_row, self.state.selected()) {
+            let rows = anchor_row.min(row)..=anchor_row.max(row);
+            marked.extend(self.view.get(rows).unwrap_or_default());
+        }
+        marked
+    }
+
+    /// Marks the selected reference, or unmarks it if it was marked, and selects the next one
+    pub fn toggle_mark(&mut self) {
+        if let Some(index) = self.selected_index() {
+            if !self.marked.remove(&index) {
+                self.marked.insert(index);
+            }
+            self.select_next();
+        }
+    }
+
+    /// Starts marking the references between the selected one and the one that will be selected, or marks
+    /// them if visual mode was already started
+    pub fn toggle_visual_mode(&mut self) {
+        match self.visual_anchor {
+            Some(_) => {
+                self.marked = self.marked_with_visual_range();
+                self.visual_anchor = None;
+            }
+            None => self.visual_anchor = self.selected_index(),
+        }
+    }
+
+    /// Marks every match of the last search. Returns how many there are.
+    pub fn mark_search_results(&mut self) -> usize {
+        self.marked.extend(&self.search_results);
+        self.search_results.len()
+    }
+
+    /// Unmarks all references and ends visual mode. Returns whether anything was marked.
+    pub fn clear_marks(&mut self) -> bool {
+        let was_marking = !self.marked.is_empty() || self.visual_anchor.is_some();
+        self.marked.clear();
+        self.visual_anchor = None;
+        was_marking
+    }
+
+    pub fn open_editor(&mut self) {
+        if let Some(index) = self.selected_index() {
+            self.editor = Some(FieldEditor::new(index, &self.items[index]));
+        }
+    }
+
+    /// Applies the changes in the editor to the reference and closes it, or shows why they can't be applied.
+    /// Returns the edited reference if it was changed.
+    pub fn save_editor(&mut self) -> Option<&Reference> {
+        let editor = self.editor.as_mut()?;
+        let index = editor.index;
+        let other_keys = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|&(other, _)| other != index)
+            .map(|(_, reference)| &reference.key);
+        match editor.reference(&self.items[index], other_keys) {
+            Ok(reference) => {
+                self.editor = None;
+                let original = &self.items[index];
+                if reference == *original && reference.entry_type == original.entry_type {
+                    return None;
+                }
+                self.edit_reference(index, reference);
+                Some(&self.items[index])
+            }
+            Err(error) => {
+                editor.error = Some(error);
+                None
+            }
+        }
+    }
+
+    /// The BibTeX of a reference: as it is in the file if it wasn't changed, and otherwise as it will be written
+    pub fn bibtex(&self, index: usize) -> String {
+        let reference = &self.items[index];
+        let block = match (&self.file, reference.origin) {
+            (Some(file), Some(origin)) if !reference.modified => file.blocks.get(origin),
+            _ => None,
+        };
+        let mut bibtex = match block {
+            Some(Block::Entry(text)) => text.clone(),
+            _ => reference.to_bibtex(),
+        };
+        if !bibtex.ends_with('\n') {
+            bibtex.push('\n');
+        }
+        bibtex
+    }
+
+    /// Replaces the reference with the given index in items by the entry in a BibTeX text, which can use the
+    /// macros of the file, or tells what is wrong with it. Returns the reference if it was changed.
+    pub fn apply_external_edit(
+        &mut self,
+        index: usize,
+        bibtex: &str,
+    ) -> Result<Option<&Reference>, String> {
+        let mut bibliography = self.parse_with_macros(bibtex)?;
+        if bibliography.references.len() != 1 {
+            return Err(format!(
+                "There should be one entry, but there are {}.",
+                bibliography.references.len()
+            ));
+        }
+        let edited = bibliography.references.remove(0);
+        let key_is_taken = self.items.iter().enumerate().any(|(other, reference)| {
+            other != index && reference.key.eq_ignore_ascii_case(&edited.key)
+        });
+        if key_is_taken {
+            return Err(format!(
+                "Another reference already has the key {}.",
+                edited.key
+            ));
+        }
+
+        let original = &self.items[index];
+        if edited == *original && edited.entry_type == original.entry_type {
+            return Ok(None);
+        }
+        let reference = Reference {
+            origin: original.origin,
+            modified: true,
+            ..edited
+        };
+        self.edit_reference(index, reference);
+        Ok(Some(&self.items[index]))
+    }
+
+    /// Adds the BibTeX entries in the clipboard as new references
+    pub fn add_references_from_clipboard(&mut self) -> Result<AddedReferences, String> {
+        let bibtex = cli_clipboard::get_contents()
+            .map_err(|_| String::from("Could not read the clipboard."))?;
+        self.add_references(&bibtex)
+    }
+
+    pub fn open_paste_buffer(&mut self) {
+        self.paste_buffer = Some(PasteBuffer {
+            input: TextInput::new("", true),
+            error: None,
+        });
+    }
+
+    /// Adds the entries in the paste buffer as new references and closes it, or shows why they can't be added
+    pub fn add_pasted_references(&mut self) -> Option<AddedReferences> {
+        let bibtex = self.paste_buffer.as_ref()?.input.text.clone();
+        match self.add_references(&bibtex) {
+            Ok(added) => {
+                self.paste_buffer = None;
+                Some(added)
+            }
+            Err(error) => {
+                if let Some(paste_buffer) = self.paste_buffer.as_mut() {
+                    paste_buffer.error = Some(error);
+                }
+                None
+            }
+        }
+    }
+
+    /// Parses BibTeX that can use the macros defined in the file, or tells where the first error in it is
+    fn parse_with_macros(&self, bibtex: &str) -> Result<Bibliography, String> {
+        let macros: String = self
+            .file
+            .iter()
+            .flat_map(|file| &file.strings)
+            .map(|(name, value)| format!("@string{{{} = {}}}\n", name, value))
+            .collect();
+        let macro_lines = macros.lines().count();
+        let bibliography = parse_bibtex(format!("{}{}", macros, bibtex), "entry");
+        match bibliography.errors.first() {
+            Some(error) => Err(format!(
+                "Line {}: {}.",
+                error.line.saturating_sub(macro_lines),
+                error.message
+            )),
+            None => Ok(bibliography),
+        }
+    }
+
+    /// Adds the entries in a BibTeX text, which can use the macros of the file, as new references. Entries
+    /// that duplicate a reference are left out, and entries whose key is taken get a letter added to it.
+    /// Nothing is added if the text has errors.
+    pub fn add_references(&mut self, bibtex: &str) -> Result<AddedReferences, String> {
+        let bibliography = self.parse_with_macros(bibtex)?;
+        if bibliography.references.is_empty() {
+            return Err(String::from("There are no BibTeX entries to add."));
+        }
+
+        let mut added = AddedReferences::default();
+        let mut changes = Vec::new();
+        let first_added = self.items.len();
+        for mut reference in bibliography.references {
+            if let Some(original) = self
+                .items
                 .iter()
-                .any(|(_field, value)| value.to_lowercase().contains(&pattern.to_lowercase()));
+                .find(|item| reference.is_duplicate_of(item))
+            {
+                added.duplicates.push((reference.key, original.key.clone()));
+                continue;
+            }
+            let key = self.unique_key(&reference.key);
+            if key != reference.key {
+                added.renamed.push((
+                    std::mem::replace(&mut reference.key, key),
+                    reference.key.clone(),
+                ));
+            }
+            reference.origin = None;
+            reference.modified = true;
+            added.keys.push(reference.key.clone());
+
+            let index = self.items.len();
+            self.insert_reference(index, reference);
+            changes.push(Change::Remove { index });
+        }
+        if added.keys.is_empty() {
+            return Ok(added);
+        }
+        let description = match added.keys.as_slice() {
+            [key] => format!("the addition of {}", key),
+            keys => format!("the addition of {} references", keys.len()),
+        };
+        self.history.record(Step {
+            description,
+            changes,
+        });
+
+        // Show the new references, with the first one selected
+        self.clear_filter();
+        let all = self.sorted((0..self.items.len()).collect());
+        self.set_view(all);
+        self.select_item(first_added);
+        Ok(added)
+    }
+
+    /// The key, or if another reference has it, the key with the first letter (or number) added to it
+    /// that makes it unique, like "Doe2020b"
+    fn unique_key(&self, key: &str) -> String {
+        let is_taken = |key: &str| {
+            self.items
+                .iter()
+                .any(|item| item.key.eq_ignore_ascii_case(key))
+        };
+        let letters = ('a'..='z').map(String::from);
+        let numbers = (2..).map(|n: usize| n.to_string());
+        std::iter::once(String::new())
+            .chain(letters)
+            .chain(numbers)
+            .map(|suffix| format!("{}{}", key, suffix))
+            .find(|candidate| !is_taken(candidate))
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    /// Removes the marked references, or else the selected one. Returns their keys.
+    pub fn delete(&mut self) -> Vec<String> {
+        let targets = self.targets();
+        let mut keys = Vec::new();
+        let mut changes = Vec::new();
+        // Removing the last ones first keeps the indices of the others the same
+        for index in targets.into_iter().rev() {
+            let reference = self.remove_reference(index);
+            keys.insert(0, reference.key.clone());
+            changes.push(Change::Insert { index, reference });
+        }
+        let description = match keys.as_slice() {
+            [key] => format!("the deletion of {}", key),
+            keys => format!("the deletion of {} references", keys.len()),
+        };
+        self.history.record(Step {
+            description,
+            changes,
+        });
+        keys
+    }
+
+    /// Adds a keyword to the marked references, or else the selected one, or removes it from them (`add` is
+    /// false). Returns the keys of the references that were changed.
+    pub fn tag(&mut self, keyword: &str, add: bool) -> Vec<String> {
+        let keyword = keyword.trim();
+        if keyword.is_empty() {
+            return Vec::new();
+        }
+        let mut keys = Vec::new();
+        let mut changes = Vec::new();
+        for index in self.targets() {
+            let mut reference = self.items[index].clone();
+            if !reference.set_keyword(keyword, add) {
+                continue;
+            }
+            reference.modified = true;
+            let previous = self.update_reference(index, reference);
+            keys.push(previous.key.clone());
+            changes.push(Change::Replace {
+                index,
+                reference: previous,
+            });
+        }
+        let references = match keys.as_slice() {
+            [key] => key.clone(),
+            keys => format!("{} references", keys.len()),
+        };
+        let description = match add {
+            true => format!("the tagging of {} with {}", references, keyword),
+            false => format!("the removal of {} from {}", keyword, references),
+        };
+        self.history.record(Step {
+            description,
+            changes,
+        });
+        keys
+    }
+
+    /// Writes the marked references, or else the selected one, to a new BibTeX file, in the order of the
+    /// library. Values that use the macros of this file are written expanded, since the new file doesn't
+    /// define them. An existing file is not overwritten. Returns the keys of the references, or why they could
+    /// not be written.
+    pub fn export(&mut self, path: &str) -> Result<Vec<String>, String> {
+        let targets = self.targets();
+        if targets.is_empty() {
+            return Err(String::from("There is nothing to export."));
+        }
+        let bibtex: Vec<String> = targets
+            .iter()
+            .map(|&index| self.items[index].to_standalone_bibtex())
+            .collect();
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(bibtex.join("\n").as_bytes()));
+        if let Err(error) = written {
+            return Err(format!("Could not write {}: {}", path, error));
+        }
+        Ok(targets
+            .iter()
+            .map(|&index| self.items[index].key.clone())
+            .collect())
+    }
+
+    /// Adds a copy of the selected reference, with a key that is not taken, and selects it. Returns the copy.
+    pub fn duplicate_selected(&mut self) -> Option<&Reference> {
+        let original = self.selected_reference()?;
+        let copy = Reference {
+            key: self.unique_key(&original.key),
+            origin: None,
+            modified: true,
+            ..original.clone()
+        };
+        let description = format!("the duplication of {}", original.key);
+        let index = self.items.len();
+        self.insert_reference(index, copy);
+        self.history.record(Step {
+            description,
+            changes: vec![Change::Remove { index }],
+        });
+        self.sort_view();
+        self.select_item(index);
+        Some(&self.items[index])
+    }
+
+    /// Undoes the last change to the references that was not undone yet. Returns what was undone.
+    pub fn undo(&mut self) -> Option<String> {
+        let step = self.history.undo.pop()?;
+        let redo = self.apply(step);
+        let description = redo.description.clone();
+        self.history.redo.push(redo);
+        Some(description)
+    }
+
+    /// Does what was last undone again. Returns what was redone.
+    pub fn redo(&mut self) -> Option<String> {
+        let step = self.history.redo.pop()?;
+        let undo = self.apply(step);
+        let description = undo.description.clone();
+        self.history.undo.push(undo);
+        Some(description)
+    }
+
+    /// Makes the changes of a step, and returns the step that reverses them
+    fn apply(&mut self, step: Step) -> Step {
+        let mut changed = None;
+        let mut reverse = Vec::new();
+        for change in step.changes.into_iter().rev() {
+            reverse.push(match change {
+                Change::Replace {
+                    index,
+                    mut reference,
+                } => {
+                    // The file may have been saved in the meantime
+                    reference.origin = self.items[index].origin;
+                    changed = Some(index);
+                    let previous = self.update_reference(index, reference);
+                    Change::Replace {
+                        index,
+                        reference: previous,
+                    }
+                }
+                Change::Insert { index, reference } => {
+                    changed = Some(index);
+                    self.insert_reference(index, reference);
+                    Change::Remove { index }
+                }
+                Change::Remove { index } => {
+                    changed = None;
+                    let reference = self.remove_reference(index);
+                    Change::Insert { index, reference }
+                }
+            });
+        }
+        // Select what was changed back, if it is still there
+        self.sort_view();
+        if let Some(index) = changed {
+            self.select_item(index);
+        }
+        Step {
+            description: step.description,
+            changes: reverse,
+        }
+    }
+
+    /// Replaces a reference by an edited version of it, in a way that can be undone
+    fn edit_reference(&mut self, index: usize, reference: Reference) {
+        let previous = self.update_reference(index, reference);
+        self.history.record(Step {
+            description: format!("the changes to {}", previous.key),
+            changes: vec![Change::Replace {
+                index,
+                reference: previous,
+            }],
+        });
+    }
+
+    /// Replaces the reference with the given index in items, and updates everything that is derived from it.
+    /// Returns the reference that was replaced.
+    fn update_reference(&mut self, index: usize, reference: Reference) -> Reference {
+        self.search_index.update(index, &reference, self.stem);
+        self.sort_keys[index] = SortKeys::new(&reference);
+        self.widen_columns(&reference);
+        let previous = std::mem::replace(&mut self.items[index], reference);
+        // The reference may match the search differently now
+        self.update_search_hit(index);
+        self.sort_view();
+        previous
+    }
+
+    /// Inserts a reference into items at the given index, so that the indices of the references from there on
+    /// go up by one, and shows it in the table unless it doesn't match the filter. Pending edits are dropped,
+    /// since they refer to indices.
+    fn insert_reference(&mut self, index: usize, reference: Reference) {
+        let shift = |i: &mut usize| {
+            if *i >= index {
+                *i += 1;
+            }
+        };
+        self.shift_indices(shift);
+        self.search_index.insert(index, &reference, self.stem);
+        self.sort_keys.insert(index, SortKeys::new(&reference));
+        self.widen_columns(&reference);
+        self.items.insert(index, reference);
+        self.update_search_hit(index);
+
+        if !self.filtered || self.search_hits.contains_key(&index) {
+            let mut view = std::mem::take(&mut self.view);
+            view.push(index);
+            self.set_view(view);
+        }
+    }
 
-            title_contains_search_string || fields_contain_search_string
+    /// Removes the reference with the given index from items, so that the indices of the references after it
+    /// go down by one. The row that it was on in the table stays selected. Pending edits are dropped, since
+    /// they refer to indices.
+    fn remove_reference(&mut self, index: usize) -> Reference {
+        self.view.retain(|&shown| shown != index);
+        self.search_hits.remove(&index);
+        self.search_results.retain(|&result| result != index);
+        self.current_match = self
+            .current_match
+            .min(self.search_results.len().saturating_sub(1));
+        self.marked.remove(&index);
+        for selection in [
+            &mut self.selection_before_filter,
+            &mut self.selection_before_search,
+            &mut self.visual_anchor,
+        ] {
+            if *selection == Some(index) {
+                *selection = None;
+            }
+        }
+        let shift = |i: &mut usize| {
+            if *i > index {
+                *i -= 1;
+            }
+        };
+        self.shift_indices(shift);
+        self.search_index.remove(index);
+        self.sort_keys.remove(index);
+        let reference = self.items.remove(index);
+
+        let view = std::mem::take(&mut self.view);
+        self.set_view(view);
+        if let Some(row) = self.state.selected() {
+            self.select(row.min(self.view.len().saturating_sub(1)));
         }
+        reference
+    }
 
-        let search_results: Vec<Reference> = match &self.status_bar {
-            StatusBar::Message(_) => Vec::new(),
-            StatusBar::Input(status_bar_input) => {
-                let search_value = status_bar_input.input.trim_start_matches('/');
+    /// Changes every index in items that is kept outside of items, after a reference was inserted or removed
+    fn shift_indices(&mut self, shift: impl Fn(&mut usize)) {
+        self.view.iter_mut().for_each(&shift);
+        self.search_results.iter_mut().for_each(&shift);
+        self.selection_before_filter.iter_mut().for_each(&shift);
+        self.selection_before_search.iter_mut().for_each(&shift);
+        self.visual_anchor.iter_mut().for_each(&shift);
+        self.marked = std::mem::take(&mut self.marked)
+            .into_iter()
+            .map(|mut index| {
+                shift(&mut index);
+                index
+            })
+            .collect();
+        self.search_hits = std::mem::take(&mut self.search_hits)
+            .into_iter()
+            .map(|(mut index, hits)| {
+                shift(&mut index);
+                (index, hits)
+            })
+            .collect();
+        self.editor = None;
+        self.external_edit = None;
+    }
+
+    /// Makes the columns of the table wide enough for a reference
+    fn widen_columns(&mut self, reference: &Reference) {
+        let lens = constraint_len_calculator(std::slice::from_ref(reference));
+        let longest = &mut self.longest_item_lens;
+        *longest = (
+            longest.0.max(lens.0),
+            longest.1.max(lens.1),
+            longest.2.max(lens.2),
+            longest.3.max(lens.3),
+            longest.4.max(lens.4),
+        );
+    }
 
-                self.items
-                    .iter()
-                    .filter(|reference| reference_contains(*reference, search_value))
-                    .cloned()
-                    .collect()
+    /// Checks whether the reference with the given index in items matches the last search
+    fn update_search_hit(&mut self, index: usize) {
+        if let Some(query) = &self.search_query {
+            let indexed_reference = &self.search_index.references[index];
+            match query.matches(indexed_reference) {
+                true => {
+                    let highlights = indexed_reference
+                        .columns
+                        .each_ref()
+                        .map(|column| query.highlight(column, self.stem));
+                    if self.search_hits.insert(index, highlights).is_none() {
+                        self.search_results.push(index);
+                    }
+                }
+                false => {
+                    self.search_hits.remove(&index);
+                    self.search_results.retain(|&result| result != index);
+                }
             }
+        }
+    }
+
+    /// Writes the changes back to the file, leaving entries that were not changed as they were. Unless
+    /// `overwrite` is set, nothing is written if the file was changed by another program since it was read.
+    /// In that case, what it contains now is kept in `conflict`.
+    pub fn save(&mut self, overwrite: bool) -> Result<(), SaveError> {
+        let file = self.file.as_ref().ok_or(SaveError::NoFile)?;
+        let on_disk = match fs::read_to_string(&file.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+        if on_disk != file.contents && !overwrite {
+            self.conflict_diff =
+                diff::with_context(&diff::diff_lines(&file.contents, &on_disk), DIFF_CONTEXT)
+                    .into_iter()
+                    .map(|line| line.map(DiffLine::to_owned))
+                    .collect();
+            self.conflict = Some(on_disk);
+            return Err(SaveError::ChangedOnDisk);
+        }
+
+        let contents = write_bibtex(&file.blocks, &self.items);
+        let backups = self
+            .backup_directory
+            .as_deref()
+            .map(|directory| (directory, self.backup_count));
+        save_bibtex(&file.path, &contents, backups)?;
+        self.clear_conflict();
+        self.read_written_file(contents);
+        Ok(())
+    }
+
+    /// Takes over the blocks of the file that was just written, so that references point to their new
+    /// entries and are no longer modified.
+    fn read_written_file(&mut self, contents: String) {
+        let Some(file) = &mut self.file else {
+            return;
         };
+        let bibliography = parse_bibtex(contents.clone(), &file.path.to_string_lossy());
+        file.blocks = bibliography.blocks;
+        file.strings = bibliography.strings;
+        file.contents = contents;
 
-        self.search_results = search_results;
+        // The references were written in the order of the entries they came from, with the new ones at the end.
+        // That is the order of the items, unless a reference that was removed from the file was put back.
+        let mut written_order: Vec<usize> = (0..self.items.len()).collect();
+        written_order
+            .sort_by_key(|&index| (self.items[index].origin.is_none(), self.items[index].origin));
+        let same_keys = bibliography.references.len() == self.items.len()
+            && bibliography
+                .references
+                .iter()
+                .zip(&written_order)
+                .all(|(written, &index)| written.key == self.items[index].key);
+        if !same_keys {
+            self.replace_items(bibliography.references);
+            return;
+        }
+        for (written, index) in bibliography.references.into_iter().zip(written_order) {
+            if self.items[index].modified || self.items[index].origin.is_none() {
+                self.update_reference(index, written);
+            } else {
+                self.items[index].origin = written.origin;
+            }
+        }
+        self.history.saved();
+    }
+
+    /// Forgets about the changes to the file by another program, after they were dealt with
+    pub fn clear_conflict(&mut self) {
+        self.conflict = None;
+        self.conflict_diff = Vec::new();
+        self.show_conflict_diff = false;
+    }
+
+    /// Combines the changes made here with those made to the file by another program, so that they can be
+    /// saved without losing either. Entries that were changed in both places get the changes made here.
+    pub fn merge_with_file(&mut self) {
+        let (Some(file), Some(on_disk)) = (&mut self.file, self.conflict.take()) else {
+            return;
+        };
+        let path = file.path.to_string_lossy().to_string();
+        let on_disk_bibliography = parse_bibtex(on_disk.clone(), &path);
+        let mut merged = on_disk_bibliography.references;
+
+        // Finds where an entry of the file as it was read is now: the same text if it wasn't changed,
+        // or otherwise the same key
+        let entry_text = |blocks: &[Block], origin: Option<usize>| match origin.map(|o| &blocks[o])
+        {
+            Some(Block::Entry(text)) => Some(text.clone()),
+            _ => None,
+        };
+        let by_text: HashMap<String, usize> = merged
+            .iter()
+            .enumerate()
+            .filter_map(|(i, reference)| {
+                Some((
+                    entry_text(&on_disk_bibliography.blocks, reference.origin)?,
+                    i,
+                ))
+            })
+            .collect();
+        let find = |origin: usize, unchanged_only: bool| {
+            let text = entry_text(&file.blocks, Some(origin))?;
+            if let Some(&i) = by_text.get(&text) {
+                return Some(i);
+            }
+            if unchanged_only {
+                return None;
+            }
+            let key = parse_bibtex(text, &path).references.first()?.key.clone();
+            merged.iter().position(|reference| reference.key == key)
+        };
+
+        let mut replaced: Vec<(usize, Reference)> = Vec::new();
+        let mut added: Vec<Reference> = Vec::new();
+        for reference in self
+            .items
+            .iter()
+            .filter(|reference| reference.modified || reference.origin.is_none())
+        {
+            match reference.origin.and_then(|origin| find(origin, false)) {
+                Some(i) => replaced.push((i, reference.clone())),
+                // References that were added here, or changed here but removed there, are added at the end
+                None => added.push(Reference {
+                    origin: None,
+                    modified: true,
+                    ..reference.clone()
+                }),
+            }
+        }
+        // References that were removed here are removed there too, unless they were changed there
+        let kept_origins: HashSet<usize> = self
+            .items
+            .iter()
+            .filter_map(|reference| reference.origin)
+            .collect();
+        let removed: Vec<usize> = file
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(origin, block)| {
+                matches!(block, Block::Entry(_)) && !kept_origins.contains(origin)
+            })
+            .filter_map(|(origin, _)| find(origin, true))
+            .collect();
+
+        for (i, reference) in replaced {
+            merged[i] = Reference {
+                origin: merged[i].origin,
+                modified: true,
+                ..reference
+            };
+        }
+        let mut merged: Vec<Reference> = merged
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !removed.contains(i))
+            .map(|(_, reference)| reference)
+            .collect();
+        merged.extend(added);
+
+        *file = SourceFile {
+            path: file.path.clone(),
+            blocks: on_disk_bibliography.blocks,
+            strings: on_disk_bibliography.strings,
+            contents: on_disk,
+        };
+        self.errors = on_disk_bibliography.errors;
+        self.clear_conflict();
+        self.replace_items(merged);
+    }
+
+    /// Replaces all references, for example after reading the file again. The search and filter are
+    /// cleared, and the selected reference stays selected if it is still there.
+    fn replace_items(&mut self, references: Vec<Reference>) {
+        let selected_key = self
+            .selected_reference()
+            .map(|reference| reference.key.clone());
+        // The indices in the history and of the marks no longer match
+        self.history.clear();
+        self.clear_marks();
+        self.clear_search();
+        self.filtered = false;
+        self.selection_before_filter = None;
+
+        self.sort_keys = references.iter().map(SortKeys::new).collect();
+        self.search_index = SearchIndex::new(&references, self.stem);
+        self.longest_item_lens = constraint_len_calculator(&references);
+        self.items = references;
+        let all = self.sorted((0..self.items.len()).collect());
+        self.set_view(all);
+
+        let selection = selected_key
+            .and_then(|key| self.items.iter().position(|reference| reference.key == key));
+        match selection {
+            Some(index) => self.select_item(index),
+            None => self.select(0),
+        }
+    }
+
+    /// The number of references that have been changed, added or removed since the file was read
+    pub fn modified_count(&self) -> usize {
+        let modified = self
+            .items
+            .iter()
+            .filter(|reference| reference.modified)
+            .count();
+        let entries = self.file.as_ref().map_or(0, |file| {
+            file.blocks
+                .iter()
+                .filter(|block| matches!(block, Block::Entry(_)))
+                .count()
+        });
+        let kept = self
+            .items
+            .iter()
+            .filter(|reference| reference.origin.is_some())
+            .count();
+        modified + entries.saturating_sub(kept)
+    }
+
+    /// Marks the references matching a query as search results, ranked by how well they match.
+    /// An empty query clears the search. Returns the number of matches.
+    pub fn search(&mut self, query: &str) -> Result<usize, QueryError> {
+        if query.trim().is_empty() {
+            self.clear_search();
+            return Ok(0);
+        }
+
+        let query = search::parse_query(query, self.stem)?;
+        self.search_results = self
+            .search_index
+            .search(&query)
+            .into_iter()
+            .map(|(index, _score)| index)
+            .collect();
+        self.search_hits = self
+            .search_results
+            .iter()
+            .map(|&index| {
+                let columns = &self.search_index.references[index].columns;
+                let highlights = columns
+                    .each_ref()
+                    .map(|column| query.highlight(column, self.stem));
+                (index, highlights)
+            })
+            .collect();
+        self.search_query = Some(query);
+        Ok(self.search_results.len())
+    }
+
+    fn clear_search(&mut self) {
+        self.search_results = Vec::new();
+        self.search_hits = HashMap::new();
+        self.search_query = None;
+    }
+
+    /// Opens the search prompt, which searches ('/') or shows only the matches ('&') while typing
+    pub fn open_search_prompt(&mut self, prompt: char) {
+        self.selection_before_search = self.selected_index();
+        self.history_position = None;
+        self.open_prompt(prompt);
+    }
+
+    /// Opens a prompt in the status bar. Besides the search prompts, there are prompts for a keyword to add to
+    /// the marked references ('+') or remove from them ('-'), and for the file to export them to ('>').
+    pub fn open_prompt(&mut self, prompt: char) {
+        self.status_bar = StatusBar::Input(StatusBarInput {
+            input: prompt.to_string(),
+            cursor_position: 1, // The starting position is 1 because the input always starts with the prompt
+        });
+    }
+
+    /// Searches for the query in the prompt once typing pauses. Small files are searched right away,
+    /// but large ones only after a short delay, so that typing stays responsive.
+    pub fn schedule_search(&mut self) {
+        let delay = match self.items.len() > LARGE_BIBLIOGRAPHY {
+            true => SEARCH_DELAY,
+            false => Duration::ZERO,
+        };
+        self.search_deadline = Some(Instant::now() + delay);
+    }
+
+    /// Searches for the query in the prompt and jumps to the best match, or goes back to the reference
+    /// that was selected before if nothing matches. Returns the number of matches.
+    pub fn run_scheduled_search(&mut self) -> Result<usize, QueryError> {
+        self.search_deadline = None;
+        let StatusBar::Input(status_bar_input) = &self.status_bar else {
+            return Ok(0);
+        };
+        // The prompt is '/' or '&', so it is always a single byte
+        let (prompt, query) = status_bar_input.input.split_at(1);
+        let (filter, query) = (prompt == "&", query.to_string());
+
+        let matches = self.search(&query)?;
+        if filter {
+            self.filter();
+        } else if matches > 0 {
+            self.select_best_match();
+        } else if let Some(selection) = self.selection_before_search {
+            self.select_item(selection);
+        }
+        Ok(matches)
+    }
+
+    /// Clears the search and the filter, and goes back to the reference that was selected before searching
+    pub fn cancel_search(&mut self) {
+        self.search_deadline = None;
+        self.clear_search();
+        self.clear_filter();
+        if let Some(selection) = self.selection_before_search.take() {
+            self.select_item(selection);
+        }
+    }
+
+    pub fn add_to_search_history(&mut self, query: &str) {
+        if query.trim().is_empty() {
+            return;
+        }
+        self.search_history.retain(|previous| previous != query);
+        self.search_history.push(query.to_string());
+        let excess = self
+            .search_history
+            .len()
+            .saturating_sub(SEARCH_HISTORY_LENGTH);
+        self.search_history.drain(..excess);
+    }
+
+    /// Puts the previous (`older`) or next search from the history in the prompt. Going past the most
+    /// recent search empties the prompt.
+    pub fn browse_search_history(&mut self, older: bool) {
+        let StatusBar::Input(status_bar_input) = &self.status_bar else {
+            return;
+        };
+        let count = self.search_history.len();
+        self.history_position = match (self.history_position, older) {
+            _ if count == 0 => return,
+            (None, true) => Some(count - 1),
+            (None, false) => return,
+            (Some(position), true) => Some(position.saturating_sub(1)),
+            (Some(position), false) if position + 1 < count => Some(position + 1),
+            (Some(_), false) => None,
+        };
+
+        let prompt = &status_bar_input.input[..1];
+        let query = match self.history_position {
+            Some(position) => self.search_history[position].as_str(),
+            None => "",
+        };
+        let input = format!("{}{}", prompt, query);
+        self.status_bar = StatusBar::Input(StatusBarInput {
+            cursor_position: input.chars().count(),
+            input,
+        });
+        self.schedule_search();
     }
 }
 
-pub fn constraint_len_calculator(items: &[Reference]) -> (u16, u16, u16, u16) {
+pub fn constraint_len_calculator(items: &[Reference]) -> (u16, u16, u16, u16, u16) {
     fn make_lines(title: Option<String>) -> Vec<String> {
         match title {
             Some(string) => string.lines().map(|s| s.to_owned()).collect(),
@@ -185,10 +1489,17 @@ pub fn constraint_len_calculator(items: &[Reference]) -> (u16, u16, u16, u16) {
         .max()
         .unwrap_or(0);
 
+    let entry_type_len = items
+        .iter()
+        .map(Reference::entry_type)
+        .map(|entry_type| UnicodeWidthStr::width(&entry_type as &str))
+        .max()
+        .unwrap_or(0);
+
     let author_len = items
         .iter()
         .map(Reference::formatted_author)
-        .flat_map(|title| make_lines(title))
+        .flat_map(make_lines)
         .map(|s| UnicodeWidthStr::width(&s as &str))
         .max()
         .unwrap_or(0);
@@ -217,8 +1528,579 @@ pub fn constraint_len_calculator(items: &[Reference]) -> (u16, u16, u16, u16) {
 
     (
         key_len as u16,
+        entry_type_len as u16,
         author_len as u16,
         year_len as u16,
         title_len as u16,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse::parse_bibtex;
+
+    fn test_app() -> App {
+        let bibtex = include_str!("../test_bibliography.bib").to_string();
+        App::new(parse_bibtex(bibtex, "test.bib").references, Vec::new())
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut app = test_app();
+        let count = app.items.len();
+        app.select_next();
+        app.select_next();
+        let selected_before = app.selected_index();
+
+        let matches = app.search("type:book").unwrap();
+        assert!(matches > 0 && matches < count);
+        app.filter();
+        assert!(app.filtered);
+        assert_eq!(matches, app.view.len());
+        assert!(app
+            .view
+            .iter()
+            .all(|&i| app.items[i].entry_type() == "book"));
+
+        // Navigation stays within the matches and wraps around
+        for _ in 0..matches {
+            app.select_next();
+            assert_eq!("book", app.selected_reference().unwrap().entry_type());
+        }
+        app.select_previous();
+        assert_eq!("book", app.selected_reference().unwrap().entry_type());
+
+        app.clear_filter();
+        assert!(!app.filtered);
+        assert_eq!(count, app.view.len());
+        assert_eq!(selected_before, app.selected_index());
+    }
+
+    #[test]
+    fn test_restored_references_match_the_filter() {
+        let mut app = test_app();
+        let not_a_book = app
+            .items
+            .iter()
+            .position(|reference| reference.entry_type() != "book")
+            .unwrap();
+        app.select_item(not_a_book);
+        app.delete();
+
+        let matches = app.search("type:book").unwrap();
+        app.filter();
+        assert!(app.undo().is_some());
+        assert_eq!(matches, app.view.len());
+        assert!(app
+            .view
+            .iter()
+            .all(|&i| app.items[i].entry_type() == "book"));
+
+        app.clear_filter();
+        assert_eq!(app.items.len(), app.view.len());
+    }
+
+    #[test]
+    fn test_filter_without_matches_shows_everything() {
+        let mut app = test_app();
+        assert_eq!(Ok(0), app.search("type:nonexistent"));
+        app.filter();
+        assert!(!app.filtered);
+        assert_eq!(app.items.len(), app.view.len());
+    }
+
+    #[test]
+    fn test_sort_keeps_selection() {
+        let mut app = test_app();
+        app.select_next();
+        app.select_next();
+        let selected = app.selected_index();
+
+        app.sort_by(SortColumn::Year);
+        assert_eq!(selected, app.selected_index());
+        let years: Vec<_> = app.view.iter().map(|&i| app.items[i].year()).collect();
+        assert!(years
+            .windows(2)
+            .all(|pair| pair[1].is_none() || pair[0] <= pair[1]));
+
+        // Sorting by the same column again reverses the order
+        app.sort_by(SortColumn::Year);
+        assert!(!app.sort_order.ascending);
+        assert_eq!(selected, app.selected_index());
+
+        // Filtering keeps the sort order
+        app.search("type:book").unwrap();
+        app.filter();
+        let mut expected = app.view.clone();
+        expected.sort_by(|&a, &b| sort::compare(&app.sort_keys, app.sort_order, a, b));
+        assert_eq!(expected, app.view);
+    }
+
+    /// An app for a copy of a small file in a temporary directory, which is removed when it is dropped
+    struct TestFile {
+        app: App,
+        directory: PathBuf,
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    fn test_file(name: &str, bibtex: &str) -> TestFile {
+        let directory =
+            std::env::temp_dir().join(format!("citeseer-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.bib");
+        fs::write(&path, bibtex).unwrap();
+        let bibliography = parse_bibtex(bibtex.to_string(), "test.bib");
+        let mut app = App::new(bibliography.references, bibliography.errors);
+        app.file = Some(SourceFile {
+            path,
+            blocks: bibliography.blocks,
+            strings: bibliography.strings,
+            contents: bibtex.to_string(),
+        });
+        TestFile { app, directory }
+    }
+
+    fn edit_title(app: &mut App, key: &str, title: &str) {
+        let index = app.items.iter().position(|r| r.key == key).unwrap();
+        let mut reference = app.items[index].clone();
+        reference.fields.insert(crate::fields::Field {
+            name: String::from("title"),
+            value: title.to_string(),
+            raw_value: None,
+        });
+        reference.modified = true;
+        app.update_reference(index, reference);
+    }
+
+    const SMALL_FILE: &str = "% Comment\n@article{a,\n  title = {First},\n}\n\n@book{b,\n  title   = {Second},\n}\n\n@misc{c,\n  title = {Third},\n}\n";
+
+    #[test]
+    fn test_save() {
+        let mut test = test_file("save", SMALL_FILE);
+        let app = &mut test.app;
+        let path = app.file.as_ref().unwrap().path.clone();
+        edit_title(app, "b", "Changed");
+        assert_eq!(1, app.modified_count());
+
+        app.save(false).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            "% Comment\n@article{a,\n  title = {First},\n}\n\n@book{b,\n    title = {Changed},\n}\n\n@misc{c,\n  title = {Third},\n}\n",
+            saved
+        );
+        assert_eq!(0, app.modified_count());
+
+        // Saving again after another change uses the entries as they were written
+        edit_title(app, "c", "Also changed");
+        app.save(false).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("@book{b,\n    title = {Changed},\n}"));
+        assert!(saved.contains("@misc{c,\n    title = {Also changed},\n}"));
+    }
+
+    #[test]
+    fn test_save_refuses_to_overwrite_changes_on_disk() {
+        let mut test = test_file("conflict", SMALL_FILE);
+        let app = &mut test.app;
+        let path = app.file.as_ref().unwrap().path.clone();
+        edit_title(app, "b", "Changed here");
+        let changed_there = SMALL_FILE.replace("{First}", "{Changed there}").replace(
+            "@misc{c,\n  title = {Third},\n}\n",
+            "@misc{d,\n  title = {New there},\n}\n",
+        );
+        fs::write(&path, &changed_there).unwrap();
+
+        assert!(matches!(app.save(false), Err(SaveError::ChangedOnDisk)));
+        assert_eq!(changed_there, fs::read_to_string(&path).unwrap());
+        assert_eq!(Some(&changed_there), app.conflict.as_ref());
+        // How the file was changed is found once, when the change is noticed
+        let changed_lines: Vec<&DiffLine<String>> = app
+            .conflict_diff
+            .iter()
+            .flatten()
+            .filter(|line| !matches!(line, DiffLine::Unchanged(_)))
+            .collect();
+        assert_eq!(
+            vec![
+                &DiffLine::Removed(String::from("  title = {First},")),
+                &DiffLine::Added(String::from("  title = {Changed there},")),
+                &DiffLine::Removed(String::from("@misc{c,")),
+                &DiffLine::Removed(String::from("  title = {Third},")),
+                &DiffLine::Added(String::from("@misc{d,")),
+                &DiffLine::Added(String::from("  title = {New there},")),
+            ],
+            changed_lines
+        );
+
+        // Merging keeps the changes made in both places
+        app.merge_with_file();
+        assert_eq!(None, app.conflict);
+        assert!(app.conflict_diff.is_empty());
+        app.save(false).unwrap();
+        assert_eq!(
+            "% Comment\n@article{a,\n  title = {Changed there},\n}\n\n@book{b,\n    title = {Changed here},\n}\n\n@misc{d,\n  title = {New there},\n}\n",
+            fs::read_to_string(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_overwrite_changes_on_disk() {
+        let mut test = test_file("overwrite", SMALL_FILE);
+        let app = &mut test.app;
+        let path = app.file.as_ref().unwrap().path.clone();
+        edit_title(app, "a", "Changed here");
+        fs::write(&path, "@misc{other, title = {Other}}\n").unwrap();
+
+        assert!(app.save(false).is_err());
+        app.save(true).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("title = {Changed here}") && !saved.contains("other"));
+    }
+
+    #[test]
+    fn test_external_edit() {
+        let bibtex = "@string{jasa = {Journal of the American Statistical Association}}\n\n@article{a,\n  title = {First},\n  journal = jasa,\n}\n\n@book{b, title = {Second}}";
+        let mut test = test_file("external-edit", bibtex);
+        let app = &mut test.app;
+        let a = app.items.iter().position(|r| r.key == "a").unwrap();
+        let b = app.items.iter().position(|r| r.key == "b").unwrap();
+
+        // The entry is edited as it is in the file
+        let original = app.bibtex(a);
+        assert_eq!(
+            "@article{a,\n  title = {First},\n  journal = jasa,\n}\n",
+            original
+        );
+        assert_eq!(
+            Ok(None),
+            app.apply_external_edit(a, &original).map(|r| r.cloned())
+        );
+
+        assert_eq!(
+            Err(String::from("Line 3: expected ',' or '}'.")),
+            app.apply_external_edit(a, "@article{a,\n  title = {First}\n  year = 2020,\n}\n")
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err(String::from("Another reference already has the key b.")),
+            app.apply_external_edit(a, "@article{b, title = {First}}")
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err(String::from("There should be one entry, but there are 2.")),
+            app.apply_external_edit(a, &format!("{}{}", original, original))
+                .map(|_| ())
+        );
+        assert_eq!(0, app.modified_count());
+
+        // Macros of the file can be used, and the reference stays where it was
+        let edited = original.replace("First", "Changed");
+        let reference = app.apply_external_edit(a, &edited).unwrap().unwrap();
+        assert_eq!(Some(&String::from("Changed")), reference.title());
+        assert_eq!(
+            Some(&String::from(
+                "Journal of the American Statistical Association"
+            )),
+            reference.fields.get("journal")
+        );
+        assert!(reference.modified);
+        assert_eq!("a", app.items[a].key);
+        assert_eq!("b", app.items[b].key);
+
+        app.save(false).unwrap();
+        let saved = fs::read_to_string(&app.file.as_ref().unwrap().path).unwrap();
+        assert!(saved.contains("@article{a,\n    title = {Changed},\n    journal = jasa,\n}"));
+    }
+
+    #[test]
+    fn test_add_references() {
+        let mut test = test_file("add", SMALL_FILE);
+        let app = &mut test.app;
+
+        assert_eq!(
+            Err(String::from("There are no BibTeX entries to add.")),
+            app.add_references("Not BibTeX")
+        );
+        assert!(app
+            .add_references("@misc{x, title = {Fine}}\n@misc{y, title = {Unclosed}")
+            .is_err());
+        assert_eq!(3, app.items.len());
+
+        // Taken keys get a letter, and duplicates of references (or of each other) are left out
+        let added = app
+            .add_references(
+                "@article{A, title = {New}}\n@misc{dup, title = {Second}}\n@book{n, title = {Fourth}}\n@book{n, title = {Fifth}}\n@book{again, title = {Fourth}}",
+            )
+            .unwrap();
+        assert_eq!(
+            AddedReferences {
+                keys: vec![String::from("Aa"), String::from("n"), String::from("na")],
+                renamed: vec![
+                    (String::from("A"), String::from("Aa")),
+                    (String::from("n"), String::from("na"))
+                ],
+                duplicates: vec![
+                    (String::from("dup"), String::from("b")),
+                    (String::from("again"), String::from("n"))
+                ],
+            },
+            added
+        );
+        assert_eq!(3, app.modified_count());
+        assert_eq!(Some("Aa"), app.selected_reference().map(|r| r.key.as_str()));
+        assert_eq!(Ok(1), app.search("Fifth"));
+
+        // New references are added at the end of the file
+        app.save(false).unwrap();
+        let saved = fs::read_to_string(&app.file.as_ref().unwrap().path).unwrap();
+        assert!(saved.starts_with(SMALL_FILE));
+        assert!(saved.find("@article{Aa,").unwrap() < saved.find("@book{na,").unwrap());
+        assert_eq!(0, app.modified_count());
+    }
+
+    #[test]
+    fn test_delete_and_undo() {
+        let mut test = test_file("undo", SMALL_FILE);
+        let app = &mut test.app;
+        let path = app.file.as_ref().unwrap().path.clone();
+        let keys = |app: &App| -> Vec<String> { app.items.iter().map(|r| r.key.clone()).collect() };
+        assert_eq!(None, app.undo());
+
+        app.select_item(1);
+        assert_eq!(vec!["b"], app.delete());
+        assert_eq!(vec!["a", "c"], keys(app));
+        assert_eq!(Some("c"), app.selected_reference().map(|r| r.key.as_str()));
+        assert_eq!(1, app.modified_count());
+
+        let mut edited = app.items[0].clone();
+        edited.key = String::from("renamed");
+        edited.modified = true;
+        app.edit_reference(0, edited);
+        app.select_item(0);
+        assert_eq!("renameda", app.duplicate_selected().unwrap().key);
+        assert_eq!(vec!["renamed", "c", "renameda"], keys(app));
+
+        // Undoing everything brings back the file as it was read
+        assert_eq!(Some(String::from("the duplication of renamed")), app.undo());
+        assert_eq!(Some(String::from("the changes to a")), app.undo());
+        assert_eq!(Some(String::from("the deletion of b")), app.undo());
+        assert_eq!(None, app.undo());
+        assert_eq!(vec!["a", "b", "c"], keys(app));
+        assert_eq!(Some("b"), app.selected_reference().map(|r| r.key.as_str()));
+        assert_eq!(0, app.modified_count());
+        assert_eq!(Ok(1), app.search("Second"));
+
+        // Redoing is possible until something else is changed
+        assert_eq!(Some(String::from("the deletion of b")), app.redo());
+        assert_eq!(Ok(0), app.search("Second"));
+        app.save(false).unwrap();
+        assert_eq!(
+            "% Comment\n@article{a,\n  title = {First},\n}\n\n@misc{c,\n  title = {Third},\n}\n",
+            fs::read_to_string(&path).unwrap()
+        );
+
+        // A reference that was deleted from the file is added at the end when the deletion is undone
+        assert_eq!(Some(String::from("the deletion of b")), app.undo());
+        assert_eq!(vec!["a", "b", "c"], keys(app));
+        assert_eq!(1, app.modified_count());
+        app.save(false).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("@misc{c,\n  title = {Third},\n}\n\n@book{b,\n    title = {Second},\n}\n"));
+        assert_eq!(0, app.modified_count());
+        assert_eq!(Some(String::from("the deletion of b")), app.redo());
+        assert_eq!(vec!["a", "c"], keys(app));
+    }
+
+    #[test]
+    fn test_marks() {
+        let mut app = test_app();
+        let count = app.items.len();
+        let rows = |app: &App, rows: &[usize]| -> BTreeSet<usize> {
+            rows.iter().map(|&row| app.view[row]).collect()
+        };
+
+        // Without marks, commands act on the selected reference
+        app.select(1);
+        assert_eq!(vec![app.view[1]], app.targets());
+
+        app.toggle_mark();
+        assert_eq!(Some(2), app.state.selected());
+        app.toggle_visual_mode();
+        app.select_next();
+        app.select_next();
+        assert_eq!(rows(&app, &[1, 2, 3, 4]), app.marked_with_visual_range());
+        app.toggle_visual_mode();
+        assert_eq!(rows(&app, &[1, 2, 3, 4]), app.marked);
+        app.select(2);
+        app.toggle_mark();
+        assert_eq!(rows(&app, &[1, 3, 4]), app.marked);
+
+        // Marked references are acted on in the order of the library
+        let targets = app.targets();
+        assert!(targets.windows(2).all(|pair| pair[0] < pair[1]));
+        let keys: Vec<String> = targets.iter().map(|&i| app.items[i].key.clone()).collect();
+        assert_eq!(keys, app.delete());
+        assert_eq!(count - 3, app.items.len());
+        assert!(app.marked.is_empty());
+        assert_eq!(
+            Some(String::from("the deletion of 3 references")),
+            app.undo()
+        );
+        assert_eq!(
+            keys,
+            targets
+                .iter()
+                .map(|&i| app.items[i].key.clone())
+                .collect::<Vec<_>>()
+        );
+
+        let matches = app.search("type:book").unwrap();
+        assert_eq!(matches, app.mark_search_results());
+        assert!(app
+            .marked
+            .iter()
+            .all(|&i| app.items[i].entry_type() == "book"));
+        assert!(app.clear_marks());
+        assert!(!app.clear_marks());
+    }
+
+    #[test]
+    fn test_tag_and_export_marked_references() {
+        let mut test = test_file(
+            "tag",
+            "@string{jasa = {J. Am. Stat. Assoc.}}\n@article{a, journal = jasa, keywords = {old}}\n\n@book{b,}\n\n@misc{c,}\n",
+        );
+        let app = &mut test.app;
+        app.marked = BTreeSet::from([0, 2]);
+        assert_eq!(vec!["a", "c"], app.tag("new", true));
+        assert_eq!(vec!["old", "new"], app.items[0].keywords());
+        assert_eq!(vec!["new"], app.items[2].keywords());
+        assert!(app.items[1].keywords().is_empty());
+        assert_eq!(2, app.modified_count());
+
+        // References that have the keyword already are left alone
+        app.marked = BTreeSet::from([0, 1]);
+        assert_eq!(vec!["b"], app.tag("new", true));
+        assert_eq!(Some(String::from("the tagging of b with new")), app.undo());
+        app.marked = BTreeSet::from([0, 2]);
+        assert_eq!(vec!["a", "c"], app.tag("new", false));
+        assert_eq!(
+            Some(String::from("the removal of new from 2 references")),
+            app.undo()
+        );
+        assert_eq!(vec!["new"], app.items[2].keywords());
+
+        // Exported references don't use the macros of the file, and existing files are not overwritten
+        app.marked = BTreeSet::from([0, 2]);
+        let path = test.directory.join("export.bib");
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            Ok(vec![String::from("a"), String::from("c")]),
+            app.export(path)
+        );
+        let exported = fs::read_to_string(path).unwrap();
+        assert_eq!(
+            "@article{a,\n    journal = {J. Am. Stat. Assoc.},\n    keywords = {old, new},\n}\n\n@misc{c,\n    keywords = {new},\n}\n",
+            exported
+        );
+        app.marked = BTreeSet::from([1]);
+        assert!(app.export(path).is_err());
+        assert_eq!(exported, fs::read_to_string(path).unwrap());
+
+        // A keyword with accents can be typed into the prompt
+        app.open_prompt('+');
+        for c in "Türkiye".chars() {
+            let StatusBar::Input(status_bar_input) = &app.status_bar else {
+                panic!("The prompt should be open");
+            };
+            app.status_bar = StatusBar::Input(crate::ui::enter_char(status_bar_input, c));
+        }
+        let StatusBar::Input(status_bar_input) = &app.status_bar else {
+            panic!("The prompt should be open");
+        };
+        let keyword = status_bar_input.input["+".len()..].to_string();
+        assert_eq!(vec!["b"], app.tag(&keyword, true));
+        assert_eq!(vec!["Türkiye"], app.items[1].keywords());
+    }
+
+    fn type_query(app: &mut App, input: &str) {
+        app.status_bar = StatusBar::Input(StatusBarInput {
+            input: input.to_string(),
+            cursor_position: input.chars().count(),
+        });
+        app.run_scheduled_search().unwrap();
+    }
+
+    #[test]
+    fn test_search_as_you_type() {
+        let mut app = test_app();
+        app.select_next();
+        let selected_before = app.selected_index();
+        app.open_search_prompt('/');
+
+        type_query(&mut app, "/type:book");
+        let best_match = app.search_results[0];
+        assert_eq!(Some(best_match), app.selected_index());
+
+        // n and N go through the matches in the order of the table
+        let row = app.state.selected().unwrap();
+        app.jump_to_next_match();
+        let next = app.selected_index().unwrap();
+        assert!(app.state.selected().unwrap() > row && app.items[next].entry_type() == "book");
+        app.jump_to_previous_match();
+        assert_eq!(Some(best_match), app.selected_index());
+
+        // Without matches, the selection goes back to where the search started
+        type_query(&mut app, "/type:nonexistent");
+        assert_eq!(selected_before, app.selected_index());
+
+        type_query(&mut app, "/type:book");
+        app.cancel_search();
+        assert!(app.search_results.is_empty());
+        assert_eq!(selected_before, app.selected_index());
+    }
+
+    #[test]
+    fn test_search_history() {
+        let mut app = test_app();
+        app.add_to_search_history("first");
+        app.add_to_search_history("second");
+        app.add_to_search_history("first");
+        assert_eq!(vec!["second", "first"], app.search_history);
+
+        app.open_search_prompt('/');
+        let input = |app: &App| match &app.status_bar {
+            StatusBar::Input(status_bar_input) => status_bar_input.input.clone(),
+            StatusBar::Message(_) => panic!("The prompt should be open"),
+        };
+        app.browse_search_history(true);
+        assert_eq!("/first", input(&app));
+        app.browse_search_history(true);
+        assert_eq!("/second", input(&app));
+        app.browse_search_history(true);
+        assert_eq!("/second", input(&app));
+        app.browse_search_history(false);
+        assert_eq!("/first", input(&app));
+        app.browse_search_history(false);
+        assert_eq!("/", input(&app));
+
+        // Typing goes on at the end of a recalled search with accents
+        app.add_to_search_history("Sözer");
+        app.browse_search_history(true);
+        let StatusBar::Input(status_bar_input) = &app.status_bar else {
+            panic!("The prompt should be open");
+        };
+        app.status_bar = StatusBar::Input(crate::ui::enter_char(status_bar_input, 'i'));
+        assert_eq!("/Sözeri", input(&app));
+        assert_eq!(Ok(1), app.run_scheduled_search());
+        assert_eq!("SözeriEtal2022", app.items[app.search_results[0]].key);
+    }
+}
@@ -0,0 +1,171 @@
+/// A line of the difference between two texts, which borrows the line (`&str`) or owns it (`String`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffLine<S> {
+    Unchanged(S),
+    Removed(S),
+    Added(S),
+}
+
+impl DiffLine<&str> {
+    /// The line with a copy of its text, which can be kept after the texts that were compared are gone
+    pub fn to_owned(self) -> DiffLine<String> {
+        match self {
+            DiffLine::Unchanged(text) => DiffLine::Unchanged(text.to_string()),
+            DiffLine::Removed(text) => DiffLine::Removed(text.to_string()),
+            DiffLine::Added(text) => DiffLine::Added(text.to_string()),
+        }
+    }
+}
+
+/// Above this number of (lines in the old text) * (lines in the new text) that differ, finding the
+/// smallest difference takes too long, and all of them are shown as removed and added instead.
+const MAX_DIFF_SIZE: usize = 4_000_000;
+
+/// The lines of `old` and `new`, marked as unchanged, removed (only in `old`) or added (only in `new`).
+///
+/// Lines that are the same at the start and end are matched up first, since a file usually only changes in
+/// a few places, and the rest is compared with the longest common subsequence of lines.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<&'a str>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_middle, new_middle) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut diff: Vec<DiffLine<&str>> = old[..prefix]
+        .iter()
+        .map(|&line| DiffLine::Unchanged(line))
+        .collect();
+    if old_middle.len() * new_middle.len() > MAX_DIFF_SIZE {
+        diff.extend(old_middle.iter().map(|&line| DiffLine::Removed(line)));
+        diff.extend(new_middle.iter().map(|&line| DiffLine::Added(line)));
+    } else {
+        diff.extend(longest_common_subsequence_diff(old_middle, new_middle));
+    }
+    diff.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|&line| DiffLine::Unchanged(line)),
+    );
+    diff
+}
+
+fn longest_common_subsequence_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<&'a str>> {
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = match old[i] == new[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            diff.push(DiffLine::Unchanged(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            // Removed lines come before the lines that replace them
+            diff.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+/// The changed lines of a diff with `context` unchanged lines around them. `None` stands for unchanged
+/// lines that are left out.
+pub fn with_context<'a>(
+    diff: &[DiffLine<&'a str>],
+    context: usize,
+) -> Vec<Option<DiffLine<&'a str>>> {
+    let changed: Vec<usize> = diff
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Unchanged(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let is_near_change = |i: usize| {
+        let next_change = changed.partition_point(|&change| change < i);
+        let after = changed.get(next_change).is_some_and(|&c| c - i <= context);
+        let before = next_change > 0 && i - changed[next_change - 1] <= context;
+        after || before
+    };
+
+    let mut lines = Vec::new();
+    for (i, line) in diff.iter().enumerate() {
+        if is_near_change(i) {
+            lines.push(Some(*line));
+        } else if lines.last().is_some_and(Option::is_some) {
+            lines.push(None);
+        }
+    }
+    // Unchanged lines at the end don't need to be marked
+    if lines.last() == Some(&None) {
+        lines.pop();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use DiffLine::*;
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(
+            vec![
+                Unchanged("a"),
+                Removed("b"),
+                Added("B"),
+                Unchanged("c"),
+                Added("d"),
+                Unchanged("e"),
+            ],
+            diff_lines("a\nb\nc\ne\n", "a\nB\nc\nd\ne\n")
+        );
+        assert_eq!(vec![Removed("a")], diff_lines("a", ""));
+        assert_eq!(Vec::<DiffLine<&str>>::new(), diff_lines("", ""));
+    }
+
+    #[test]
+    fn test_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        let diff = diff_lines(old, new);
+        assert_eq!(
+            vec![
+                Some(Unchanged("4")),
+                Some(Removed("5")),
+                Some(Added("five")),
+                Some(Unchanged("6")),
+            ],
+            with_context(&diff, 1)
+        );
+
+        let new = "one\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let diff = diff_lines(old, new);
+        assert_eq!(
+            vec![Some(Removed("1")), Some(Added("one")), Some(Unchanged("2"))],
+            with_context(&diff, 1)
+        );
+    }
+}
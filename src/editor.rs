@@ -0,0 +1,563 @@
+use crate::{
+    fields::{Field, Fields},
+    reference::Reference,
+};
+
+/// Characters that can't be used in keys and field names, because BibTeX would read them as part of the
+/// syntax of the entry.
+const RESERVED_CHARACTERS: [char; 11] = [',', '{', '}', '(', ')', '"', '#', '%', '\'', '=', '\\'];
+
+/// A text that is being typed, which can span several lines.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextInput {
+    pub text: String,
+    /// The position of the cursor, in characters
+    pub cursor: usize,
+    /// Whether Enter starts a new line, or the text has to stay on one line
+    pub multiline: bool,
+}
+
+impl TextInput {
+    pub fn new(text: &str, multiline: bool) -> Self {
+        Self {
+            text: text.to_string(),
+            cursor: text.chars().count(),
+            multiline,
+        }
+    }
+
+    pub fn insert(&mut self, c: char) {
+        if c == '\n' && !self.multiline {
+            return;
+        }
+        let index = self.byte_index(self.cursor);
+        self.text.insert(index, c);
+        self.cursor += 1;
+    }
+
+    /// Inserts pasted text at the cursor, with Windows and old Mac line endings turned into newlines
+    pub fn insert_str(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let text: String = match self.multiline {
+            true => text,
+            false => text.chars().filter(|&c| c != '\n').collect(),
+        };
+        let index = self.byte_index(self.cursor);
+        self.text.insert_str(index, &text);
+        self.cursor += text.chars().count();
+    }
+
+    /// Removes the character before the cursor, like Backspace
+    pub fn delete_before(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            let index = self.byte_index(self.cursor);
+            self.text.remove(index);
+        }
+    }
+
+    /// Removes the character after the cursor, like Delete
+    pub fn delete_after(&mut self) {
+        if self.cursor < self.text.chars().count() {
+            let index = self.byte_index(self.cursor);
+            self.text.remove(index);
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.text.chars().count());
+    }
+
+    /// Moves to the start of the line
+    pub fn move_home(&mut self) {
+        let (_, column) = self.line_and_column();
+        self.cursor -= column;
+    }
+
+    /// Moves to the end of the line
+    pub fn move_end(&mut self) {
+        let (line, column) = self.line_and_column();
+        let length = self
+            .text
+            .split('\n')
+            .nth(line)
+            .unwrap_or("")
+            .chars()
+            .count();
+        self.cursor += length - column;
+    }
+
+    /// Moves to the same column in the previous line, or the end of it if it is shorter
+    pub fn move_up(&mut self) {
+        let (line, column) = self.line_and_column();
+        if line > 0 {
+            self.move_to(line - 1, column);
+        }
+    }
+
+    pub fn move_down(&mut self) {
+        let (line, column) = self.line_and_column();
+        if line + 1 < self.text.split('\n').count() {
+            self.move_to(line + 1, column);
+        }
+    }
+
+    fn move_to(&mut self, line: usize, column: usize) {
+        let lines: Vec<&str> = self.text.split('\n').collect();
+        // Every line before it is followed by a newline
+        let start: usize = lines[..line]
+            .iter()
+            .map(|line| line.chars().count() + 1)
+            .sum();
+        self.cursor = start + column.min(lines[line].chars().count());
+    }
+
+    /// The line the cursor is on and its position in that line, in characters
+    pub fn line_and_column(&self) -> (usize, usize) {
+        let before: Vec<char> = self.text.chars().take(self.cursor).collect();
+        let line = before.iter().filter(|&&c| c == '\n').count();
+        let column = before.iter().rev().take_while(|&&c| c != '\n').count();
+        (line, column)
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.text.len(), |(index, _)| index)
+    }
+}
+
+/// Which part of a row of the form is being edited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditedPart {
+    Name,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditedField {
+    pub name: String,
+    pub value: String,
+    /// The field as it was before editing, which is kept as it is (with its raw value) if it isn't changed
+    original: Option<Field>,
+}
+
+/// A form for editing the entry type, key and fields of a reference.
+///
+/// The rows of the form are the entry type, the key and then the fields, in order. Changes are only made
+/// to the reference when the form is saved, after checking that the result is valid BibTeX.
+#[derive(Debug, Clone)]
+pub struct FieldEditor {
+    /// The index in items of the reference that is edited
+    pub index: usize,
+    pub entry_type: String,
+    pub key: String,
+    pub fields: Vec<EditedField>,
+    /// The selected row
+    pub selected: usize,
+    /// The text that is being typed, and which part of the selected row it goes into
+    pub input: Option<(EditedPart, TextInput)>,
+    /// Why the form couldn't be saved
+    pub error: Option<String>,
+    /// Whether the selected field was just added, so that its value is edited after its name
+    adding: bool,
+}
+
+/// The number of rows before the fields: the entry type and the key
+pub const FIXED_ROWS: usize = 2;
+
+impl FieldEditor {
+    pub fn new(index: usize, reference: &Reference) -> Self {
+        Self {
+            index,
+            entry_type: reference.entry_type.clone(),
+            key: reference.key.clone(),
+            fields: reference
+                .fields
+                .iter()
+                .map(|field| EditedField {
+                    name: field.name.clone(),
+                    value: field.value.clone(),
+                    original: Some(field.clone()),
+                })
+                .collect(),
+            selected: 0,
+            input: None,
+            error: None,
+            adding: false,
+        }
+    }
+
+    /// The name and value of every row
+    pub fn rows(&self) -> Vec<(&str, &str)> {
+        [
+            ("type", self.entry_type.as_str()),
+            ("key", self.key.as_str()),
+        ]
+        .into_iter()
+        .chain(
+            self.fields
+                .iter()
+                .map(|field| (field.name.as_str(), field.value.as_str())),
+        )
+        .collect()
+    }
+
+    fn row_count(&self) -> usize {
+        FIXED_ROWS + self.fields.len()
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.row_count();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = (self.selected + self.row_count() - 1) % self.row_count();
+    }
+
+    /// Starts editing the value of the selected row. Only field values can span several lines.
+    pub fn edit_value(&mut self) {
+        let (_, value) = self.rows()[self.selected];
+        let multiline = self.selected >= FIXED_ROWS;
+        self.input = Some((EditedPart::Value, TextInput::new(value, multiline)));
+    }
+
+    /// Starts editing the name of the selected field. The entry type and key can't be renamed.
+    pub fn rename(&mut self) {
+        if let Some(field) = self.selected_field() {
+            let input = TextInput::new(&field.name, false);
+            self.input = Some((EditedPart::Name, input));
+        }
+    }
+
+    /// Adds an empty field after the selected one, and starts editing its name
+    pub fn add_field(&mut self) {
+        let position = self.selected.max(FIXED_ROWS - 1) + 1;
+        self.fields.insert(
+            position - FIXED_ROWS,
+            EditedField {
+                name: String::new(),
+                value: String::new(),
+                original: None,
+            },
+        );
+        self.selected = position;
+        self.adding = true;
+        self.input = Some((EditedPart::Name, TextInput::default()));
+    }
+
+    pub fn delete_field(&mut self) {
+        if self.selected_field().is_some() {
+            self.fields.remove(self.selected - FIXED_ROWS);
+            self.selected = self.selected.min(self.row_count() - 1);
+        }
+    }
+
+    /// Puts the typed text into the form. After the name of a new field, its value is edited.
+    pub fn finish_input(&mut self) {
+        let Some((part, input)) = self.input.take() else {
+            return;
+        };
+        let text = input.text;
+        match (self.selected, part) {
+            (0, _) => self.entry_type = text.trim().to_string(),
+            (1, _) => self.key = text.trim().to_string(),
+            (row, EditedPart::Name) => self.fields[row - FIXED_ROWS].name = text.trim().to_string(),
+            (row, EditedPart::Value) => self.fields[row - FIXED_ROWS].value = text,
+        }
+
+        if self.adding {
+            match self.selected_field() {
+                // A new field without a name is dropped again
+                Some(field) if field.name.is_empty() => self.delete_field(),
+                Some(_) if part == EditedPart::Name => {
+                    self.edit_value();
+                    return;
+                }
+                _ => {}
+            }
+            self.adding = false;
+        }
+    }
+
+    fn selected_field(&self) -> Option<&EditedField> {
+        self.selected
+            .checked_sub(FIXED_ROWS)
+            .and_then(|i| self.fields.get(i))
+    }
+
+    /// The reference as edited, or why it is not valid. `other_keys` are the keys of the other references,
+    /// which the key must differ from. The reference is marked as modified if anything changed.
+    pub fn reference(
+        &self,
+        original: &Reference,
+        mut other_keys: impl Iterator<Item = impl AsRef<str>>,
+    ) -> Result<Reference, String> {
+        if self.entry_type.is_empty() || !self.entry_type.chars().all(char::is_alphanumeric) {
+            return Err(format!(
+                "The entry type \"{}\" must be a single word, like article or book.",
+                self.entry_type
+            ));
+        }
+        check_name("key", &self.key)?;
+        if other_keys.any(|key| key.as_ref().eq_ignore_ascii_case(&self.key)) {
+            return Err(format!(
+                "Another reference already has the key {}.",
+                self.key
+            ));
+        }
+
+        let mut fields = Fields::new();
+        for (i, field) in self.fields.iter().enumerate() {
+            check_name("field name", &field.name)?;
+            if self.fields[..i]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&field.name))
+            {
+                return Err(format!("There are two {} fields.", field.name));
+            }
+            if !has_balanced_braces(&field.value) {
+                return Err(format!("The braces in {} are not balanced.", field.name));
+            }
+
+            // Unchanged fields are kept as they were read, so that macros in them aren't expanded. The form
+            // shows values as LaTeX, so they are written as they were typed.
+            fields.insert(match &field.original {
+                Some(original) if original.name == field.name && original.value == field.value => {
+                    original.clone()
+                }
+                _ => Field {
+                    name: field.name.clone(),
+                    value: field.value.clone(),
+                    raw_value: Some(format!("{{{}}}", field.value)),
+                },
+            });
+        }
+
+        let mut reference = Reference {
+            entry_type: self.entry_type.clone(),
+            key: self.key.clone(),
+            fields,
+            ..original.clone()
+        };
+        reference.modified = original.modified
+            || reference.entry_type != original.entry_type
+            || reference.key != original.key
+            || reference.fields != original.fields;
+        Ok(reference)
+    }
+}
+
+/// Checks that a key or field name is not empty and doesn't contain spaces or characters that have a
+/// meaning in BibTeX.
+fn check_name(what: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("The {} can't be empty.", what));
+    }
+    match name
+        .chars()
+        .find(|c| c.is_whitespace() || RESERVED_CHARACTERS.contains(c))
+    {
+        Some(c) if c.is_whitespace() => Err(format!("The {} {} contains a space.", what, name)),
+        Some(c) => Err(format!("The {} {} can't contain '{}'.", what, name, c)),
+        None => Ok(()),
+    }
+}
+
+/// Whether every brace in the value is closed, and only after it was opened. Escaped braces (`\{`) don't count.
+fn has_balanced_braces(value: &str) -> bool {
+    let mut depth: usize = 0;
+    let mut previous = None;
+    for c in value.chars() {
+        match c {
+            '{' if previous != Some('\\') => depth += 1,
+            '}' if previous != Some('\\') => match depth.checked_sub(1) {
+                Some(new_depth) => depth = new_depth,
+                None => return false,
+            },
+            _ => {}
+        }
+        previous = Some(c);
+    }
+    depth == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse::parse_bibtex;
+
+    fn test_reference() -> Reference {
+        let bibtex = String::from(
+            "@string{jasa = {Journal of the American Statistical Association}}\n\
+             @article{doe2020,\n  title = {A Titel},\n  journal = jasa,\n  year = 2020,\n}",
+        );
+        parse_bibtex(bibtex, "test.bib").references.remove(0)
+    }
+
+    fn type_text(editor: &mut FieldEditor, text: &str) {
+        let (_, input) = editor.input.as_mut().unwrap();
+        text.chars().for_each(|c| input.insert(c));
+    }
+
+    #[test]
+    fn test_text_input() {
+        let mut input = TextInput::new("first\nsecond line", true);
+        assert_eq!((1, 11), input.line_and_column());
+        input.move_up();
+        assert_eq!((0, 5), input.line_and_column());
+        input.move_home();
+        input.insert('ä');
+        input.move_down();
+        assert_eq!((1, 1), input.line_and_column());
+        input.move_end();
+        input.delete_before();
+        input.insert('\n');
+        assert_eq!("äfirst\nsecond lin\n", input.text);
+        input.move_left();
+        input.move_left();
+        input.delete_after();
+        assert_eq!("äfirst\nsecond li\n", input.text);
+
+        let mut single_line = TextInput::new("key", false);
+        single_line.insert('\n');
+        assert_eq!("key", single_line.text);
+        single_line.move_home();
+        single_line.insert_str("the\r\n");
+        assert_eq!(
+            ("thekey", 3),
+            (single_line.text.as_str(), single_line.cursor)
+        );
+
+        let mut multiline = TextInput::new("", true);
+        multiline.insert_str("@misc{a,\r\n}\r");
+        assert_eq!("@misc{a,\n}\n", multiline.text);
+        assert_eq!((2, 0), multiline.line_and_column());
+    }
+
+    #[test]
+    fn test_edit_fields() {
+        let original = test_reference();
+        let mut editor = FieldEditor::new(0, &original);
+        let no_keys = std::iter::empty::<&str>;
+
+        // Fix the typo in the title
+        editor.selected = FIXED_ROWS;
+        editor.edit_value();
+        let (_, input) = editor.input.as_mut().unwrap();
+        input.delete_before();
+        input.delete_before();
+        type_text(&mut editor, "le");
+        editor.finish_input();
+
+        // Rename a field, add one and delete one
+        editor.select_next();
+        editor.select_next();
+        editor.rename();
+        let (_, input) = editor.input.as_mut().unwrap();
+        *input = TextInput::new("date", false);
+        editor.finish_input();
+        editor.add_field();
+        type_text(&mut editor, "note");
+        editor.finish_input();
+        assert_eq!(
+            Some(EditedPart::Value),
+            editor.input.as_ref().map(|(p, _)| *p)
+        );
+        type_text(&mut editor, "First line\nsecond line");
+        editor.finish_input();
+        editor.select_previous();
+        editor.select_previous();
+        editor.delete_field();
+
+        let reference = editor.reference(&original, no_keys()).unwrap();
+        assert!(reference.modified);
+        let names: Vec<&str> = reference.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(vec!["title", "date", "note"], names);
+        assert_eq!(Some(&String::from("A Title")), reference.title());
+        assert_eq!(
+            "@article{doe2020,\n    title = {A Title},\n    date = {2020},\n    note = {First line\nsecond line},\n}\n",
+            reference.to_bibtex()
+        );
+
+        // Saving without changes keeps the reference as it was
+        let unchanged = FieldEditor::new(0, &original)
+            .reference(&original, no_keys())
+            .unwrap();
+        assert!(!unchanged.modified);
+        assert_eq!(original.fields, unchanged.fields);
+    }
+
+    #[test]
+    fn test_edited_values_are_latex() {
+        let original = test_reference();
+        let mut editor = FieldEditor::new(0, &original);
+        editor.fields[0].value = String::from("Caf{\\'e} $x^2$!");
+        editor.add_field();
+        type_text(&mut editor, "url");
+        editor.finish_input();
+        type_text(&mut editor, "https://x.org/a_b%20c");
+        editor.finish_input();
+
+        let reference = editor
+            .reference(&original, std::iter::empty::<&str>())
+            .unwrap();
+        let bibtex = reference.to_bibtex();
+        assert!(bibtex.contains("title = {Caf{\\'e} $x^2$!},"));
+        assert!(bibtex.contains("url = {https://x.org/a_b%20c},"));
+    }
+
+    #[test]
+    fn test_new_field_without_name_is_dropped() {
+        let original = test_reference();
+        let mut editor = FieldEditor::new(0, &original);
+        editor.add_field();
+        editor.finish_input();
+        assert_eq!(original.fields.iter().count(), editor.fields.len());
+        assert_eq!(None, editor.input);
+    }
+
+    #[test]
+    fn test_validation() {
+        let original = test_reference();
+        let invalid = |change: &dyn Fn(&mut FieldEditor)| {
+            let mut editor = FieldEditor::new(0, &original);
+            change(&mut editor);
+            editor
+                .reference(&original, ["Taken"].into_iter())
+                .unwrap_err()
+        };
+
+        assert_eq!(
+            "The key can't be empty.",
+            invalid(&|editor| editor.key = String::new())
+        );
+        assert_eq!(
+            "Another reference already has the key taken.",
+            invalid(&|editor| editor.key = String::from("taken"))
+        );
+        assert_eq!(
+            "The key doe 2020 contains a space.",
+            invalid(&|editor| editor.key = String::from("doe 2020"))
+        );
+        assert_eq!(
+            "The field name a,b can't contain ','.",
+            invalid(&|editor| editor.fields[0].name = String::from("a,b"))
+        );
+        assert_eq!(
+            "There are two TITLE fields.",
+            invalid(&|editor| editor.fields[1].name = String::from("TITLE"))
+        );
+        assert_eq!(
+            "The braces in title are not balanced.",
+            invalid(&|editor| editor.fields[0].value = String::from("{A} Title}"))
+        );
+        assert!(has_balanced_braces("{\\\"o} and \\{"));
+    }
+}
@@ -0,0 +1,110 @@
+/// A single `name = value` pair of a reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// The name with the casing it has in the file
+    pub name: String,
+    /// The value, with `@string` macros expanded
+    pub value: String,
+    /// The value as it appears in the file, without expanding macros (`journal = jasa # " 2020"`).
+    /// This is `None` for values that were not read from a file or typed as LaTeX, but given as Unicode text,
+    /// which is encoded into LaTeX when it is written.
+    pub raw_value: Option<String>,
+}
+
+/// The fields of a reference, in the order in which they were added.
+///
+/// Field names are case-insensitive, as in BibTeX: `get("title")` finds `Title = {...}` as well,
+/// while the original casing is kept for writing the field back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields(Vec<Field>);
+
+impl Fields {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.get_field(name).map(|field| &field.value)
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&Field> {
+        self.0
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Adds a field. If a field with the same name already exists, it is replaced, but keeps its position
+    /// and the casing of its name.
+    pub fn insert(&mut self, field: Field) {
+        match self
+            .0
+            .iter_mut()
+            .find(|existing| existing.name.eq_ignore_ascii_case(&field.name))
+        {
+            Some(existing) => {
+                existing.value = field.value;
+                existing.raw_value = field.raw_value;
+            }
+            None => self.0.push(field),
+        }
+    }
+
+    /// Removes the field with the name, if there is one
+    pub fn remove(&mut self, name: &str) {
+        self.0
+            .retain(|field| !field.name.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Field> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse::parse_bibtex;
+
+    fn field(name: &str, value: &str) -> Field {
+        Field {
+            name: name.to_string(),
+            value: value.to_string(),
+            raw_value: None,
+        }
+    }
+
+    #[test]
+    fn test_fields_are_case_insensitive_and_ordered() {
+        let mut fields = Fields::new();
+        fields.insert(field("Title", "A title"));
+        fields.insert(field("YEAR", "2020"));
+        fields.insert(field("author", "Doe, Jane"));
+
+        assert_eq!(Some(&String::from("A title")), fields.get("title"));
+        assert_eq!(Some(&String::from("2020")), fields.get("Year"));
+        assert_eq!(None, fields.get("journal"));
+
+        // Replacing a field keeps its position and original casing
+        fields.insert(field("year", "2021"));
+        let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+        assert_eq!(vec!["Title", "YEAR", "author"], names);
+        assert_eq!(Some(&String::from("2021")), fields.get("year"));
+    }
+
+    #[test]
+    fn test_reference_accessors_ignore_field_case() {
+        let bibtex = String::from(
+            "@Article{Doe2020,\n  TITLE = {Upper},\n  Year = 2020,\n  AUTHOR = {Doe, Jane},\n  journal = {J},\n}",
+        );
+        let reference = &parse_bibtex(bibtex, "test.bib").references[0];
+
+        assert_eq!(Some(&String::from("Upper")), reference.title());
+        assert_eq!(Some(&String::from("2020")), reference.year());
+        assert_eq!(Some(String::from("Doe, J")), reference.formatted_author());
+        assert_eq!(
+            "@Article{Doe2020,\n    TITLE = {Upper},\n    Year = 2020,\n    AUTHOR = {Doe, Jane},\n    journal = {J},\n}\n",
+            reference.to_bibtex()
+        );
+    }
+}
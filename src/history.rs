@@ -0,0 +1,70 @@
+use crate::reference::Reference;
+
+/// How many steps can be undone. Older ones are forgotten.
+const MAX_STEPS: usize = 1000;
+
+/// A change to the list of references, described by how to reverse it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Put this reference back at the index, in place of the one that is there now
+    Replace { index: usize, reference: Reference },
+    /// Insert this reference at the index
+    Insert { index: usize, reference: Reference },
+    /// Remove the reference at the index
+    Remove { index: usize },
+}
+
+/// Something the user did, which is undone or redone as a whole, even if it changed many references.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// What was done, like "the deletion of Doe2020"
+    pub description: String,
+    /// The changes that reverse it, in the order in which it made them. They are applied in the reverse order.
+    pub changes: Vec<Change>,
+}
+
+/// The steps that can be undone, most recent last, and the steps that were undone and can be redone.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub undo: Vec<Step>,
+    pub redo: Vec<Step>,
+}
+
+impl History {
+    /// Remembers a step so that it can be undone. What was undone before can no longer be redone.
+    pub fn record(&mut self, step: Step) {
+        if step.changes.is_empty() {
+            return;
+        }
+        self.undo.push(step);
+        if self.undo.len() > MAX_STEPS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// After the file was saved, the references that undoing or redoing brings back are no longer what is
+    /// in it: they are written again, and those that were removed from the file are added at the end.
+    pub fn saved(&mut self) {
+        let changes = self
+            .undo
+            .iter_mut()
+            .chain(&mut self.redo)
+            .flat_map(|step| &mut step.changes);
+        for change in changes {
+            match change {
+                Change::Replace { reference, .. } => reference.modified = true,
+                Change::Insert { reference, .. } => {
+                    reference.origin = None;
+                    reference.modified = true;
+                }
+                Change::Remove { .. } => {}
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
@@ -0,0 +1,373 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Accent commands and the Unicode combining characters they correspond to.
+const ACCENTS: [(&str, char); 16] = [
+    ("`", '\u{300}'),
+    ("'", '\u{301}'),
+    ("^", '\u{302}'),
+    ("~", '\u{303}'),
+    ("=", '\u{304}'),
+    ("u", '\u{306}'),
+    (".", '\u{307}'),
+    ("\"", '\u{308}'),
+    ("r", '\u{30A}'),
+    ("H", '\u{30B}'),
+    ("v", '\u{30C}'),
+    ("d", '\u{323}'),
+    ("c", '\u{327}'),
+    ("k", '\u{328}'),
+    ("b", '\u{331}'),
+    ("t", '\u{361}'),
+];
+
+/// Commands that produce a single character or string.
+const SYMBOLS: [(&str, &str); 62] = [
+    ("ss", "ß"),
+    ("SS", "SS"),
+    ("ae", "æ"),
+    ("AE", "Æ"),
+    ("oe", "œ"),
+    ("OE", "Œ"),
+    ("aa", "å"),
+    ("AA", "Å"),
+    ("o", "ø"),
+    ("O", "Ø"),
+    ("l", "ł"),
+    ("L", "Ł"),
+    ("i", "ı"),
+    ("j", "ȷ"),
+    ("dh", "ð"),
+    ("DH", "Ð"),
+    ("th", "þ"),
+    ("TH", "Þ"),
+    ("ng", "ŋ"),
+    ("NG", "Ŋ"),
+    ("dj", "đ"),
+    ("DJ", "Đ"),
+    ("textendash", "–"),
+    ("textemdash", "—"),
+    ("textquoteleft", "‘"),
+    ("textquoteright", "’"),
+    ("textquotedblleft", "“"),
+    ("textquotedblright", "”"),
+    ("guillemotleft", "«"),
+    ("guillemotright", "»"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("textellipsis", "…"),
+    ("textasciitilde", "~"),
+    ("textbackslash", "\\"),
+    ("backslash", "\\"),
+    ("textbar", "|"),
+    ("textdegree", "°"),
+    ("textregistered", "®"),
+    ("texttrademark", "™"),
+    ("copyright", "©"),
+    ("textcopyright", "©"),
+    ("S", "§"),
+    ("P", "¶"),
+    ("pounds", "£"),
+    ("euro", "€"),
+    ("LaTeX", "LaTeX"),
+    ("TeX", "TeX"),
+    ("BibTeX", "BibTeX"),
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("pi", "π"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("phi", "φ"),
+    ("chi", "χ"),
+    ("omega", "ω"),
+];
+
+/// Characters that have to be escaped with a backslash in BibTeX values.
+const SPECIAL_CHARACTERS: [char; 5] = ['&', '%', '$', '#', '_'];
+
+/// Decodes a BibTeX value from LaTeX into plain Unicode text, for displaying, sorting and searching.
+///
+/// Accents (`{\"o}`, `\'{e}`), special letters (`{\ss}`, `\o`), dashes (`--`, `---`), quotes,
+/// escaped characters (`\&`) and common symbols are replaced by the characters they represent.
+/// Text formatting commands like `\textit{...}` and `\emph{...}` are replaced by their argument,
+/// and grouping braces and math delimiters are removed.
+pub fn decode(latex: &str) -> String {
+    // Most values are plain text, which doesn't need to be decoded
+    if latex.is_ascii() && !latex.contains(['\\', '{', '}', '$', '~', '-', '`', '\'']) {
+        return latex.to_string();
+    }
+    let mut decoder = Decoder {
+        chars: latex.chars().collect(),
+        position: 0,
+    };
+    let decoded = decoder.decode_group(false);
+    match decoded.is_ascii() {
+        true => decoded,
+        false => decoded.nfc().collect(),
+    }
+}
+
+/// Encodes plain Unicode text into LaTeX that can be read by BibTeX, the inverse of [`decode`].
+///
+/// Accented letters and special letters are replaced by their (braced) commands, dashes and quotes by
+/// their LaTeX ligatures, and `&`, `%`, `$`, `#` and `_` are escaped unless they already are.
+/// Braces and backslashes are kept, so encoding a value that is already LaTeX leaves it unchanged.
+pub fn encode(text: &str) -> String {
+    let mut encoded = String::new();
+    let mut previous: Option<char> = None;
+
+    for c in text.nfc() {
+        match c {
+            c if SPECIAL_CHARACTERS.contains(&c) && previous != Some('\\') => {
+                encoded.push('\\');
+                encoded.push(c);
+            }
+            '–' => encoded.push_str("--"),
+            '—' => encoded.push_str("---"),
+            '“' => encoded.push_str("``"),
+            '”' => encoded.push_str("''"),
+            '‘' => encoded.push('`'),
+            '’' => encoded.push('\''),
+            c if c.is_ascii() => encoded.push(c),
+            c => encoded.push_str(&encode_char(c)),
+        }
+        previous = Some(c);
+    }
+
+    encoded
+}
+
+/// Encodes a single non-ASCII character as a braced LaTeX command, or keeps it if there is no such command.
+fn encode_char(c: char) -> String {
+    if let Some((command, _)) = SYMBOLS
+        .iter()
+        .find(|(_, symbol)| symbol.chars().eq(std::iter::once(c)))
+    {
+        return format!("{{\\{}}}", command);
+    }
+
+    // Split accented letters into the letter and its accents, e.g. "ö" into "o" and U+0308
+    let mut decomposed = std::iter::once(c).nfd();
+    let Some(base) = decomposed.next() else {
+        return c.to_string();
+    };
+    let mut encoded = match base {
+        // An accented i or j loses its dot
+        'i' | 'j' => format!("\\{}", base),
+        base if base.is_ascii() => base.to_string(),
+        _ => return c.to_string(),
+    };
+    for mark in decomposed {
+        let Some((command, _)) = ACCENTS.iter().find(|(_, accent)| *accent == mark) else {
+            return c.to_string();
+        };
+        // Letter commands need braces around their argument, `\v{s}`, and so do commands as arguments,
+        // `\'{\i}`; a letter after a symbol command doesn't, `\"o`
+        encoded = if command.chars().all(|c| c.is_ascii_alphabetic()) || encoded.starts_with('\\') {
+            format!("\\{}{{{}}}", command, encoded)
+        } else {
+            format!("\\{}{}", command, encoded)
+        };
+    }
+
+    format!("{{{}}}", encoded)
+}
+
+struct Decoder {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Decoder {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn peek_is(&self, offset: usize, expected: char) -> bool {
+        self.chars.get(self.position + offset) == Some(&expected)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        Some(c)
+    }
+
+    /// Decodes text up to the end of the current braced group (or the end of the input).
+    fn decode_group(&mut self, in_braces: bool) -> String {
+        let mut decoded = String::new();
+
+        while let Some(c) = self.bump() {
+            match c {
+                '}' if in_braces => break,
+                '{' => decoded.push_str(&self.decode_group(true)),
+                '}' | '$' => {}
+                '\\' => decoded.push_str(&self.decode_command()),
+                '~' => decoded.push(' '),
+                '-' if self.peek_is(0, '-') && self.peek_is(1, '-') => {
+                    self.position += 2;
+                    decoded.push('—');
+                }
+                '-' if self.peek_is(0, '-') => {
+                    self.position += 1;
+                    decoded.push('–');
+                }
+                '`' if self.peek_is(0, '`') => {
+                    self.position += 1;
+                    decoded.push('“');
+                }
+                '\'' if self.peek_is(0, '\'') => {
+                    self.position += 1;
+                    decoded.push('”');
+                }
+                '`' => decoded.push('‘'),
+                c => decoded.push(c),
+            }
+        }
+
+        decoded
+    }
+
+    /// Decodes a command, assuming the backslash has already been consumed.
+    fn decode_command(&mut self) -> String {
+        let Some(first) = self.bump() else {
+            return String::from("\\");
+        };
+
+        let name: String = if first.is_ascii_alphabetic() {
+            let mut name = first.to_string();
+            while let Some(c) = self.peek().filter(char::is_ascii_alphabetic) {
+                name.push(c);
+                self.position += 1;
+            }
+            // Spaces after a command name only end the name
+            while self.peek().is_some_and(char::is_whitespace) {
+                self.position += 1;
+            }
+            name
+        } else {
+            first.to_string()
+        };
+
+        if let Some((_, mark)) = ACCENTS.iter().find(|(command, _)| *command == name) {
+            let mut argument = self.decode_argument();
+            // Put the accent after the first letter of the argument, so it can be composed with it
+            let first_len = argument.chars().next().map_or(0, char::len_utf8);
+            argument.insert(first_len, *mark);
+            return argument;
+        }
+
+        if let Some((_, symbol)) = SYMBOLS.iter().find(|(command, _)| *command == name) {
+            return symbol.to_string();
+        }
+
+        match name.as_str() {
+            // Forced spaces and line breaks
+            " " | "," | ";" | ":" | "\\" => String::from(" "),
+            // Hyphenation hints and italic corrections
+            "-" | "/" => String::new(),
+            name if !first.is_ascii_alphabetic() => name.to_string(),
+            // Formatting commands like \textit{...} and unknown commands are replaced by their argument
+            _ if self.peek() == Some('{') => {
+                self.position += 1;
+                self.decode_group(true)
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Decodes the argument of an accent command: a braced group, a dotless i or j, or a single character.
+    fn decode_argument(&mut self) -> String {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+        match self.bump() {
+            Some('{') => self
+                .decode_group(true)
+                .chars()
+                .map(|c| match c {
+                    'ı' => 'i',
+                    'ȷ' => 'j',
+                    c => c,
+                })
+                .collect(),
+            // An accent on a dotless i or j replaces its dot
+            Some('\\') if matches!(self.peek(), Some('i' | 'j')) => {
+                self.bump().map(String::from).unwrap_or_default()
+            }
+            Some(c) => c.to_string(),
+            None => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!("Gödel", decode("G{\\\"o}del"));
+        assert_eq!("Gödel", decode("G\\\"{o}del"));
+        assert_eq!("Gödel", decode("G\\\"odel"));
+        assert_eq!("Gårdhus", decode("G\\r{a}rdhus"));
+        assert_eq!(
+            "Güçlütürk, Yağmur",
+            decode("G{\\\"u}{\\c{c}}l{\\\"u}t{\\\"u}rk, Ya{\\u{g}}mur")
+        );
+        assert_eq!("García", decode("Garc{\\'i}a"));
+        assert_eq!("García", decode("Garc{\\'\\i}a"));
+        assert_eq!("Straße", decode("Stra{\\ss}e"));
+        assert_eq!("Straße", decode("Stra\\ss e"));
+        assert_eq!("Łódź", decode("{\\L}{\\'o}d{\\'z}"));
+        assert_eq!("Čapek", decode("\\v{C}apek"));
+        assert_eq!("106–125", decode("106--125"));
+        assert_eq!("yes—no", decode("yes---no"));
+        assert_eq!("Big Data & Society", decode("Big Data \\& Society"));
+        assert_eq!("86.2%", decode("86.2{\\%}"));
+        assert_eq!("John Wiley & Sons", decode("John Wiley {\\&} Sons"));
+        assert_eq!(
+            "A study of Deep things",
+            decode("A study of \\textit{Deep} things")
+        );
+        assert_eq!("An emphasized word", decode("An \\emph{emphasized} word"));
+        assert_eq!("NASA missions", decode("{{NASA}} missions"));
+        assert_eq!("“Big Five”", decode("``Big Five''"));
+        assert_eq!("Effects in α-decay", decode("Effects in $\\alpha$-decay"));
+        assert_eq!("C\\:/Users", decode("C$\\backslash$:/Users"));
+        assert_eq!("A B", decode("A~B"));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!("G{\\\"o}del", encode("Gödel"));
+        assert_eq!("Stra{\\ss}e", encode("Straße"));
+        assert_eq!("Garc{\\'{\\i}}a", encode("García"));
+        assert_eq!("{\\v{C}}apek", encode("Čapek"));
+        assert_eq!("{\\c{C}}", encode("Ç"));
+        assert_eq!("106--125", encode("106–125"));
+        assert_eq!("Big Data \\& Society", encode("Big Data & Society"));
+        assert_eq!("50\\% of \\$100", encode("50% of $100"));
+        assert_eq!("``Big Five''", encode("“Big Five”"));
+        // Values that are already LaTeX are not changed
+        assert_eq!("Big Data \\& Society", encode("Big Data \\& Society"));
+        assert_eq!("G{\\\"o}del", encode("G{\\\"o}del"));
+        // Characters without a LaTeX command are kept
+        assert_eq!("日本", encode("日本"));
+
+        for text in [
+            "Gödel",
+            "Łódź",
+            "García",
+            "Čapek",
+            "Straße",
+            "“Big” – Five & more",
+        ] {
+            assert_eq!(text, decode(&encode(text)));
+        }
+    }
+}
@@ -1,31 +1,70 @@
-// To do:
-// - Make field parsing case-insensitive
-
 mod app;
+mod diff;
+mod editor;
+mod fields;
+mod history;
+mod latex;
+mod normalize;
 mod parse;
 mod reference;
+mod search;
+mod sort;
+mod template;
 mod ui;
+mod write;
+mod yank;
 
-use std::{error::Error, fs, io, path::PathBuf, process::exit};
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    fs,
+    hash::{BuildHasher, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{exit, Command},
+    time::Instant,
+};
 
-use app::App;
+use app::{AddedReferences, App, ExternalEdit, SaveError, StatusBarInput};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{
+        self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 use parse::parse_bibtex;
 use ratatui::prelude::*;
+use reference::Reference;
+use sort::CollationKey;
 use ui::{delete_char, ui};
+use write::{write_bibtex, SourceFile};
 
-use crate::{
-    app::{StatusBar, StatusBarInput},
-    ui::enter_char,
-};
+use crate::{app::StatusBar, editor::TextInput, ui::enter_char};
+
+/// How many backups of a file are kept by default
+const DEFAULT_BACKUP_COUNT: usize = 5;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = std::env::args().collect::<Vec<String>>();
+    // With --check, we only report problems in the file instead of starting the TUI
+    let check = args.iter().any(|arg| arg == "--check");
+    // With --stem, searching for "studies" also finds "study"
+    let stem = args.iter().any(|arg| arg == "--stem");
+    // With --export=KEY, the references are printed in the yank format with that key instead of starting the TUI
+    let export = args.iter().find_map(|arg| arg.strip_prefix("--export="));
+    // With --backups=N, the N most recent versions of the file are kept when it is saved
+    let backup_count = args
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--backups="))
+        .map_or(DEFAULT_BACKUP_COUNT, |count| {
+            count.parse().unwrap_or_else(|_| {
+                println!("--backups must be a number, not {}.", count);
+                exit(1);
+            })
+        });
     // 1. Try to get path from args
     // 2. Try to get path from ~/.citeseer
     // 3. Exit with message
@@ -38,21 +77,83 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     set_last_bibliography_file(&path_str);
 
-    let bibtex_string =
-        fs::read_to_string(&path_str).expect(format!("Failed to open file: {}", path_str).as_str());
+    let bibtex_string = fs::read_to_string(&path_str).unwrap_or_else(|error| {
+        println!("Failed to open file {}: {}", path_str, error);
+        exit(1);
+    });
+
+    let bibliography = parse_bibtex(bibtex_string.clone(), &path_str);
+
+    if check {
+        for error in &bibliography.errors {
+            println!("{}\n", error);
+        }
+        // Editing is only safe if an unchanged file is written back exactly as it was
+        if write_bibtex(&bibliography.blocks, &bibliography.references) != bibtex_string {
+            println!("Warning: {} would not be written back unchanged.", path_str);
+        }
+        println!(
+            "{} references, {} errors",
+            bibliography.references.len(),
+            bibliography.errors.len()
+        );
+        exit(if bibliography.errors.is_empty() { 0 } else { 1 });
+    }
+
+    let yank_formats = get_yank_formats().unwrap_or_else(|error| {
+        println!("Error in the config file: {}", error);
+        exit(1);
+    });
 
-    let references =
-        parse_bibtex(bibtex_string).expect(format!("Failed to parse file: {}", path_str).as_str());
+    if let Some(format_key) = export {
+        let format = yank_formats
+            .iter()
+            .find(|format| format.key.to_string() == format_key)
+            .unwrap_or_else(|| {
+                println!("There is no format for {}.", format_key);
+                exit(1);
+            });
+        // In the order of a bibliography: by author, year and title
+        let mut references = bibliography.references;
+        references.sort_by_cached_key(CollationKey::new);
+        let references: Vec<&Reference> = references.iter().collect();
+        match format.apply(&references) {
+            Ok(text) => println!("{}", text.trim_end()),
+            Err(error) => {
+                println!("{}", error);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
 
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    execute!(
+        stdout,
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
-    let app = App::new(references);
+    let mut app = App::new(bibliography.references, bibliography.errors);
+    app.file = Some(SourceFile {
+        path: PathBuf::from(&path_str),
+        blocks: bibliography.blocks,
+        strings: bibliography.strings,
+        contents: bibtex_string,
+    });
+    app.backup_directory = backup_directory();
+    app.backup_count = backup_count;
+    app.search_history = get_search_history();
+    app.yank_formats = yank_formats;
+    if stem {
+        app.set_stemming(true);
+    }
     let res = run_app(&mut terminal, app);
 
     // restore terminal
@@ -60,7 +161,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
-        DisableMouseCapture
+        DisableMouseCapture,
+        DisableBracketedPaste
     )?;
     terminal.show_cursor()?;
 
@@ -71,11 +173,12 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn get_path_str(args: &Vec<String>) -> Option<String> {
-    if args.len() == 1 {
-        return None;
-    }
-    Some(args[1].clone())
+fn get_path_str(args: &[String]) -> Option<String> {
+    // The first argument is the name of the program, and flags start with "--"
+    args.iter()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .cloned()
 }
 
 fn get_last_bibliography_file() -> Option<String> {
@@ -89,20 +192,78 @@ fn set_last_bibliography_file(bibliography_path: &String) -> Option<()> {
     fs::write(path, bibliography_path).ok()
 }
 
-fn run_app<'a, B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
+/// The yank formats in the config file, with the default ones that it doesn't replace
+fn get_yank_formats() -> Result<Vec<yank::YankFormat>, String> {
+    let config = config_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    yank::parse_formats(&config, yank::default_formats())
+}
+
+fn get_search_history() -> Vec<String> {
+    search_history_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|contents| contents.lines().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+fn set_search_history(search_history: &[String]) -> Option<()> {
+    let path = search_history_path()?;
+    fs::write(path, search_history.join("\n")).ok()
+}
+
+fn run_app<B: Backend + io::Write>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
     loop {
         terminal.draw(|frame| ui(frame, &mut app))?;
+        // While a search is scheduled, only wait for a key until it is due
+        if let Some(deadline) = app.search_deadline {
+            if !event::poll(deadline.saturating_duration_since(Instant::now()))? {
+                // The query is often incomplete while it is being typed, so errors are only shown on Enter
+                let _ = app.run_scheduled_search();
+                continue;
+            }
+        }
         // TODO make input a general widget, instead of putting it in ui
-        if let Event::Key(key) = event::read()? {
-            if key.kind == KeyEventKind::Press {
+        let event = event::read()?;
+        // Clicking or pasting in between the keys of a command of two keys cancels it
+        let interrupts = match &event {
+            Event::Mouse(mouse) => mouse.kind != MouseEventKind::Moved,
+            Event::Paste(_) => true,
+            _ => false,
+        };
+        if interrupts && app.pending_key.take().is_some() {
+            app.status_bar = StatusBar::Message(String::default());
+        }
+        // Clicking on the header of a column sorts the table by it
+        if let Event::Mouse(mouse) = event {
+            if mouse.kind == MouseEventKind::Down(MouseButton::Left)
+                && matches!(app.status_bar, StatusBar::Message(_))
+                && app.click_header(mouse.column, mouse.row)
+            {
+                show_sort_order(&mut app);
+            }
+        }
+        // Pasted text goes into the text that is being typed, and otherwise it is added as BibTeX entries
+        if let Event::Paste(text) = &event {
+            handle_paste(&mut app, text);
+        }
+        if let Event::Key(key) = event {
+            if key.kind == KeyEventKind::Press && app.paste_buffer.is_some() {
+                handle_paste_buffer_input(&mut app, key);
+            } else if key.kind == KeyEventKind::Press && app.editor.is_some() {
+                handle_editor_input(&mut app, key);
+            } else if key.kind == KeyEventKind::Press && app.conflict.is_some() {
+                handle_conflict_input(&mut app, key.code);
+            } else if key.kind == KeyEventKind::Press {
                 match app.status_bar {
                     // If the status bar is displaying a message, we are in the state where
                     // we should handle keypresses as key commands (h, j, k, q, etc.)
-                    StatusBar::Message(_) => {
-                        if handle_keyboard_command(&mut app, key.code) {
-                            return Ok(());
-                        }
-                    }
+                    StatusBar::Message(_) => match handle_keyboard_command(&mut app, key) {
+                        Next::Quit => return Ok(()),
+                        // The external editor takes over the terminal, so it is started here
+                        Next::EditExternally => edit_in_external_editor(terminal, &mut app)?,
+                        Next::Done => {}
+                    },
                     // Otherwise, we should handle keypresses as status bar input
                     StatusBar::Input(_) => {
                         app.status_bar = handle_status_bar_input(&mut app, key);
@@ -117,61 +278,605 @@ fn settings_path() -> Option<PathBuf> {
     let mut path = dirs::home_dir()?;
     path.push(".citeseer");
     path.push("settings");
-    return Some(path);
+    Some(path)
+}
+
+fn config_path() -> Option<PathBuf> {
+    let mut path = dirs::home_dir()?;
+    path.push(".citeseer");
+    path.push("config");
+    Some(path)
+}
+
+fn backup_directory() -> Option<PathBuf> {
+    let mut path = dirs::home_dir()?;
+    path.push(".citeseer");
+    path.push("backups");
+    Some(path)
+}
+
+fn search_history_path() -> Option<PathBuf> {
+    let mut path = dirs::home_dir()?;
+    path.push(".citeseer");
+    path.push("search_history");
+    Some(path)
 }
 
-fn handle_keyboard_command(app: &mut App, key_code: KeyCode) -> bool {
+/// What the event loop has to do after a key command was handled
+enum Next {
+    Done,
+    Quit,
+    EditExternally,
+}
+
+fn handle_keyboard_command(app: &mut App, key: event::KeyEvent) -> Next {
     use KeyCode::*;
-    if key_code == Char('q') {
-        return true;
+    // The second key of a command of two keys
+    if let Some(pending) = app.pending_key.take() {
+        match (pending, key.code) {
+            ('d', Char('d')) => {
+                let keys = app.delete();
+                if !keys.is_empty() {
+                    app.status_bar = StatusBar::Message(format!(
+                        "Deleted {}. Press u to undo.",
+                        describe_keys(&keys)
+                    ));
+                }
+            }
+            ('y', Char(format_key)) => {
+                app.status_bar = StatusBar::Message(match app.yank(format_key) {
+                    Ok((format, keys)) => format!(
+                        "Copied {} to the clipboard as {}.",
+                        describe_keys(&keys),
+                        format.name
+                    ),
+                    Err(error) => error,
+                });
+            }
+            _ => app.status_bar = StatusBar::Message(String::default()),
+        }
+        return Next::Done;
     }
-    match key_code {
+    match key.code {
+        // Q quits without saving
+        Char('Q') => return Next::Quit,
+        Char('q') => match app.modified_count() {
+            0 => return Next::Quit,
+            count => {
+                app.status_bar = StatusBar::Message(format!(
+                    "{} {} unsaved changes. Press w to save, or Q to quit without saving.",
+                    count,
+                    if count == 1 {
+                        "reference has"
+                    } else {
+                        "references have"
+                    }
+                ))
+            }
+        },
+        Char('w') => save(app, false),
         Char('j') | Down => app.select_next(),
         Char('k') | Up => app.select_previous(),
         Char('l') | Right => app.next_color(),
-        Char('h') | Left => app.previous_color(),
-        Char('y') => match app.yank() {
-            Some(reference) => {
+        Char('!') => app.toggle_errors(),
+        Char('p') => app.toggle_details(),
+        Char('E') => app.open_editor(),
+        // e edits the BibTeX of the selected reference in $VISUAL or $EDITOR
+        Char('e') => return Next::EditExternally,
+        // 'a' adds the BibTeX entries in the clipboard, and 'A' opens a text box to paste or type them in
+        Char('a') => {
+            app.status_bar = StatusBar::Message(match app.add_references_from_clipboard() {
+                Ok(added) => added_message(&added),
+                Err(error) => error,
+            });
+        }
+        Char('A') => app.open_paste_buffer(),
+        // dd deletes the marked references or the selected one, and D adds a copy of the selected one
+        Char('d') => {
+            let marked = app.marked_with_visual_range();
+            let keys: Vec<String> = match marked.is_empty() {
+                true => app
+                    .selected_reference()
+                    .map(|r| r.key.clone())
+                    .into_iter()
+                    .collect(),
+                false => marked
+                    .iter()
+                    .map(|&index| app.items[index].key.clone())
+                    .collect(),
+            };
+            if !keys.is_empty() {
                 app.status_bar = StatusBar::Message(format!(
-                    "Copied {} to the clipboard as BibTeX.",
-                    reference.key
+                    "Press d again to delete {}.",
+                    describe_keys(&keys)
                 ));
+                app.pending_key = Some('d');
+            }
+        }
+        Char('D') => {
+            let original = app.selected_reference().map(|r| r.key.clone());
+            if let (Some(original), Some(copy)) = (original, app.duplicate_selected()) {
+                app.status_bar = StatusBar::Message(format!(
+                    "Added {}, a copy of {}. Press E to edit it.",
+                    copy.key, original
+                ));
+            }
+        }
+        // u undoes changes to the references, and Ctrl-r redoes them
+        Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.status_bar = StatusBar::Message(match app.redo() {
+                Some(description) => format!("Redid {}.", description),
+                None => String::from("There is nothing to redo."),
+            });
+        }
+        Char('u') => {
+            app.status_bar = StatusBar::Message(match app.undo() {
+                Some(description) => format!("Undid {}.", description),
+                None => String::from("There is nothing to undo."),
+            });
+        }
+        // The errors are scrolled while they are shown, since they are only shown for a moment
+        Char('J') if app.show_errors => app.scroll_errors_down(),
+        Char('K') if app.show_errors => app.scroll_errors_up(),
+        Char('J') => app.scroll_details_down(),
+        Char('K') => app.scroll_details_up(),
+        Char('h') | Left => app.previous_color(),
+        Tab => app.select_next_match(),
+        BackTab => app.select_previous_match(),
+        // y and then the key of a format copies the references in that format, like yy for BibTeX
+        Char('y') => {
+            let formats: Vec<String> = app
+                .yank_formats
+                .iter()
+                .map(|format| format!("{}: {}", format.key, format.name))
+                .collect();
+            app.status_bar = StatusBar::Message(format!("Copy as   {}", formats.join("   ")));
+            app.pending_key = Some('y');
+        }
+        // Space marks the selected reference, V marks a range, and M marks the matches of the search
+        Char(' ') => app.toggle_mark(),
+        Char('V') => {
+            app.toggle_visual_mode();
+            app.status_bar = StatusBar::Message(match app.visual_anchor {
+                Some(_) => String::from("Move to mark a range, and press V to mark it."),
+                None => format!("{} marked.", app.marked.len()),
+            });
+        }
+        Char('M') => {
+            let count = app.mark_search_results();
+            app.status_bar = StatusBar::Message(match count {
+                0 => String::from("There are no search results to mark."),
+                count => format!("Marked {} search results.", count),
+            });
+        }
+        // '/' searches, '&' searches and shows only the matches
+        Char(prompt @ ('/' | '&')) => app.open_search_prompt(prompt),
+        // t adds a keyword to the marked references or the selected one, T removes one, and W writes them to
+        // another file
+        Char('t') => app.open_prompt('+'),
+        Char('T') => app.open_prompt('-'),
+        Char('W') => app.open_prompt('>'),
+        // 's' goes through the sort orders, and 'S' reverses the order
+        Char('s') => {
+            app.sort_by_next_column();
+            show_sort_order(app);
+        }
+        Char('S') => {
+            app.reverse_sort_order();
+            show_sort_order(app);
+        }
+        Char('n') => app.jump_to_next_match(),
+        Char('N') => app.jump_to_previous_match(),
+        // Esc backs out of one thing at a time
+        Esc => match app.external_edit.take() {
+            Some(_) => {
+                app.status_bar = StatusBar::Message(String::from("Discarded the changes."));
+            }
+            None => {
+                if !app.clear_marks() {
+                    app.clear_filter();
+                }
             }
-            None => app.status_bar = StatusBar::Message(String::from("Yank failed.")),
         },
-        Char('/') => {
-            let status_bar_input = StatusBarInput {
-                input: String::from("/"),
-                cursor_position: 1, // The starting position is 1 because the search field always contains a '/'
-            };
+        _ => {}
+    }
+    Next::Done
+}
+
+/// Lets the user edit the BibTeX of the selected reference in their own editor (`$VISUAL` or `$EDITOR`), and
+/// replaces the reference with the result. If it can't be used, the user can edit it again.
+fn edit_in_external_editor<B: Backend + io::Write>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+) -> io::Result<()> {
+    let retrying = app.external_edit.is_some();
+    let ExternalEdit { index, bibtex } = match app.external_edit.take() {
+        Some(external_edit) => external_edit,
+        None => match app.selected_index() {
+            Some(index) => ExternalEdit {
+                index,
+                bibtex: app.bibtex(index),
+            },
+            None => return Ok(()),
+        },
+    };
+    let path = match create_temp_file(&bibtex) {
+        Ok(path) => path,
+        Err(error) => {
+            app.status_bar =
+                StatusBar::Message(format!("Could not create a file for the editor: {}", error));
+            // Changes that couldn't be used yet can still be edited again
+            if retrying {
+                app.external_edit = Some(ExternalEdit { index, bibtex });
+            }
+            return Ok(());
+        }
+    };
+
+    // The editor needs the terminal as it normally is
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableBracketedPaste
+    )?;
+    let status = run_external_editor(&path);
+    enable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        EnterAlternateScreen,
+        EnableMouseCapture,
+        EnableBracketedPaste
+    )?;
+    terminal.clear()?;
+
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    let message = match (status, edited) {
+        (Err(error), _) => format!("Could not start the editor: {}", error),
+        (Ok(false), _) => {
+            String::from("The editor exited with an error, so the changes were discarded.")
+        }
+        (Ok(true), Err(error)) => format!("Could not read the edited entry: {}", error),
+        (Ok(true), Ok(edited)) => match app.apply_external_edit(index, &edited) {
+            Ok(Some(reference)) => format!("Changed {}. Press w to save.", reference.key),
+            Ok(None) => String::from("The entry was not changed."),
+            Err(error) => {
+                app.external_edit = Some(ExternalEdit {
+                    index,
+                    bibtex: edited,
+                });
+                format!(
+                    "{} Press e to edit the entry again, or Esc to discard the changes.",
+                    error
+                )
+            }
+        },
+    };
+    app.status_bar = StatusBar::Message(message);
+    Ok(())
+}
+
+/// Creates a file with the text in the temporary directory, and returns its path. Its name is random and it
+/// is only created if it doesn't exist yet, so that nothing put there in advance (like a link to another file)
+/// is written to or read back. On Unix, only the user can read it.
+fn create_temp_file(text: &str) -> io::Result<PathBuf> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    loop {
+        // The standard library has no random numbers, but the keys of its hash maps are random
+        let suffix = RandomState::new().build_hasher().finish();
+        let path = std::env::temp_dir().join(format!("citeseer-{:016x}.bib", suffix));
+        match options.open(&path) {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes())?;
+                return Ok(path);
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Opens the file in `$VISUAL` or `$EDITOR`, or vi if neither is set, and waits until it is closed.
+/// Returns whether the editor exited successfully.
+fn run_external_editor(path: &Path) -> io::Result<bool> {
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| String::from("vi"));
+    // The variable can contain arguments, like "code --wait"
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program).args(words).arg(path).status()?;
+    Ok(status.success())
+}
+
+/// Handles keys while the editor is open. While a name or value is typed, keys go into it, and otherwise
+/// they act on the form.
+fn handle_editor_input(app: &mut App, key: event::KeyEvent) {
+    use KeyCode::*;
+    let Some(editor) = app.editor.as_mut() else {
+        return;
+    };
+
+    // Ctrl-s saves the form, also while typing
+    if key.code == Char('s') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        editor.finish_input();
+        save_editor(app);
+        return;
+    }
+
+    if let Some((_, input)) = editor.input.as_mut() {
+        match key.code {
+            // Values can span several lines, so Enter only finishes typing a key or a name
+            Enter if !input.multiline => editor.finish_input(),
+            Tab | Esc => editor.finish_input(),
+            key_code => edit_text(input, key_code),
+        }
+        return;
+    }
+
+    match key.code {
+        Char('j') | Down => editor.select_next(),
+        Char('k') | Up => editor.select_previous(),
+        Char('i') | Enter => editor.edit_value(),
+        Char('r') => editor.rename(),
+        Char('a') => editor.add_field(),
+        Char('d') => editor.delete_field(),
+        Char('w') => save_editor(app),
+        // Closing the editor without saving discards the changes
+        Char('q') | Esc => app.editor = None,
+        _ => {}
+    }
+}
+
+/// Handles keys while BibTeX entries are pasted or typed into the paste buffer
+fn handle_paste_buffer_input(app: &mut App, key: event::KeyEvent) {
+    let Some(paste_buffer) = app.paste_buffer.as_mut() else {
+        return;
+    };
+    match key.code {
+        KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            if let Some(added) = app.add_pasted_references() {
+                app.status_bar = StatusBar::Message(added_message(&added));
+            }
+        }
+        KeyCode::Esc => app.paste_buffer = None,
+        key_code => edit_text(&mut paste_buffer.input, key_code),
+    }
+}
+
+/// Handles pasted text: it is inserted where the user is typing, or opens the paste buffer with it
+fn handle_paste(app: &mut App, text: &str) {
+    let input = match (&mut app.paste_buffer, &mut app.editor) {
+        (Some(paste_buffer), _) => &mut paste_buffer.input,
+        (None, Some(editor)) => match editor.input.as_mut() {
+            Some((_, input)) => input,
+            None => return,
+        },
+        (None, None)
+            if matches!(app.status_bar, StatusBar::Message(_)) && app.conflict.is_none() =>
+        {
+            app.open_paste_buffer();
+            match app.paste_buffer.as_mut() {
+                Some(paste_buffer) => &mut paste_buffer.input,
+                None => return,
+            }
+        }
+        (None, None) => return,
+    };
+    input.insert_str(text);
+}
 
-            app.status_bar = StatusBar::Input(status_bar_input);
+/// Handles keys that edit a text that is being typed
+fn edit_text(input: &mut TextInput, key_code: KeyCode) {
+    use KeyCode::*;
+    match key_code {
+        Enter => input.insert('\n'),
+        Char(c) => input.insert(c),
+        Backspace => input.delete_before(),
+        Delete => input.delete_after(),
+        Left => input.move_left(),
+        Right => input.move_right(),
+        Up => input.move_up(),
+        Down => input.move_down(),
+        Home => input.move_home(),
+        End => input.move_end(),
+        _ => {}
+    }
+}
+
+/// Handles the input of the prompt for a keyword to add ('+') or remove ('-'), or a file to export to ('>'),
+/// which are used on Enter
+fn handle_prompt_input(app: &mut App, input: StatusBarInput, key: event::KeyEvent) -> StatusBar {
+    use KeyCode::*;
+    match key.code {
+        Backspace => StatusBar::Input(delete_char(&input)),
+        Esc => StatusBar::Message(String::default()),
+        Enter => {
+            let (prompt, text) = input.input.split_at(1);
+            let text = text.trim();
+            StatusBar::Message(match prompt {
+                _ if text.is_empty() => String::default(),
+                ">" => match app.export(text) {
+                    Ok(keys) => format!("Wrote {} to {}.", describe_keys(&keys), text),
+                    Err(error) => error,
+                },
+                _ => {
+                    let add = prompt == "+";
+                    let keys = app.tag(text, add);
+                    match (keys.is_empty(), add) {
+                        (true, true) => {
+                            format!("Every reference already has the keyword {}.", text)
+                        }
+                        (true, false) => format!("No reference has the keyword {}.", text),
+                        (false, true) => format!(
+                            "Added the keyword {} to {}. Press u to undo.",
+                            text,
+                            describe_keys(&keys)
+                        ),
+                        (false, false) => format!(
+                            "Removed the keyword {} from {}. Press u to undo.",
+                            text,
+                            describe_keys(&keys)
+                        ),
+                    }
+                }
+            })
+        }
+        Char(c) => StatusBar::Input(enter_char(&input, c)),
+        _ => StatusBar::Input(input),
+    }
+}
+
+/// The key of a single reference, or the number of references, for messages like "Deleted Doe2020."
+fn describe_keys(keys: &[String]) -> String {
+    match keys {
+        [key] => key.clone(),
+        keys => format!("{} references", keys.len()),
+    }
+}
+
+/// Describes which references were added, which were renamed and which were left out
+fn added_message(added: &AddedReferences) -> String {
+    let mut message = match added.keys.len() {
+        0 => String::from("Nothing was added"),
+        1 => format!("Added {}", added.keys[0]),
+        count => format!("Added {} references", count),
+    };
+    if !added.renamed.is_empty() {
+        let renamed: Vec<String> = added
+            .renamed
+            .iter()
+            .map(|(old, new)| format!("{} renamed to {}", old, new))
+            .collect();
+        message.push_str(&format!(" ({})", renamed.join(", ")));
+    }
+    message.push('.');
+    for (key, original) in &added.duplicates {
+        message.push_str(&format!(" Skipped {}, a duplicate of {}.", key, original));
+    }
+    if !added.keys.is_empty() {
+        message.push_str(" Press w to save.");
+    }
+    message
+}
+
+/// Writes the changes to the file, or asks what to do if it was changed by another program
+fn save(app: &mut App, overwrite: bool) {
+    let count = app.modified_count();
+    let message = match app.save(overwrite) {
+        Ok(()) => format!(
+            "Saved {} to {}.",
+            if count == 1 { String::from("1 change") } else { format!("{} changes", count) },
+            app.file.as_ref().map_or(String::new(), |file| file.path.display().to_string())
+        ),
+        Err(SaveError::ChangedOnDisk) => String::from(
+            "The file was changed by another program. d: show changes, m: merge, o: overwrite, Esc: cancel",
+        ),
+        Err(error) => format!("Saving failed: {}", error),
+    };
+    app.status_bar = StatusBar::Message(message);
+}
+
+/// Handles keys while asking what to do about changes to the file by another program
+fn handle_conflict_input(app: &mut App, key_code: KeyCode) {
+    use KeyCode::*;
+    match key_code {
+        Char('d') => {
+            app.show_conflict_diff = !app.show_conflict_diff;
+            app.conflict_diff_scroll = 0;
+        }
+        Char('j') | Down => app.conflict_diff_scroll = app.conflict_diff_scroll.saturating_add(1),
+        Char('k') | Up => app.conflict_diff_scroll = app.conflict_diff_scroll.saturating_sub(1),
+        Char('m') => {
+            app.merge_with_file();
+            app.status_bar = StatusBar::Message(String::from(
+                "Merged with the changes in the file. Press w to save.",
+            ));
+        }
+        Char('o') => save(app, true),
+        Esc | Char('q') => {
+            app.clear_conflict();
+            app.status_bar = StatusBar::Message(String::from("The file was not saved."));
         }
         _ => {}
     }
-    false
+}
+
+fn save_editor(app: &mut App) {
+    if let Some(reference) = app.save_editor() {
+        app.status_bar = StatusBar::Message(format!("Changed {}. Press w to save.", reference.key));
+    }
+}
+
+fn show_sort_order(app: &mut App) {
+    let direction = match app.sort_order.ascending {
+        true => "ascending",
+        false => "descending",
+    };
+    app.status_bar = StatusBar::Message(format!(
+        "Sorted by {} ({}).",
+        app.sort_order.column.name(),
+        direction
+    ));
 }
 
 fn handle_status_bar_input(app: &mut App, key: event::KeyEvent) -> StatusBar {
     match &app.status_bar {
         StatusBar::Message(_) => app.status_bar.clone(), // This can never happen
+        // The prompts of the commands on the marked references don't search
+        StatusBar::Input(status_bar_input) if !status_bar_input.input.starts_with(['/', '&']) => {
+            handle_prompt_input(app, status_bar_input.clone(), key)
+        }
         StatusBar::Input(status_bar_input) => {
             use KeyCode::*;
             match key.code {
-                // Backspace removes characters
+                // Backspace removes characters, and the search is updated while typing
                 Backspace => {
-                    let sb = delete_char(&status_bar_input);
+                    let sb = delete_char(status_bar_input);
+                    app.schedule_search();
                     StatusBar::Input(sb)
                 }
-                // ESC resets the status bar to displaying a (blank) message
-                Esc => StatusBar::Message(String::default()),
-                // Enter performs the search
-                Enter => {
-                    app.search();
+                // ESC cancels the search and resets the status bar to displaying a (blank) message
+                Esc => {
+                    app.cancel_search();
                     StatusBar::Message(String::default())
                 }
+                // Enter confirms the search
+                Enter => {
+                    let query = status_bar_input.input[1..].to_string();
+                    let result = app.run_scheduled_search();
+                    app.add_to_search_history(&query);
+                    set_search_history(&app.search_history);
+                    match result {
+                        Ok(0) if !query.trim().is_empty() => {
+                            StatusBar::Message(String::from("No references match the search."))
+                        }
+                        Ok(_) => StatusBar::Message(String::default()),
+                        Err(error) => StatusBar::Message(format!("Invalid search: {}", error)),
+                    }
+                }
+                // Up and Down go through previous searches
+                Up => {
+                    app.browse_search_history(true);
+                    app.status_bar.clone()
+                }
+                Down => {
+                    app.browse_search_history(false);
+                    app.status_bar.clone()
+                }
                 // Any other char should be entered into the input field
-                Char(c) => StatusBar::Input(enter_char(&status_bar_input, c)),
+                Char(c) => {
+                    let sb = enter_char(status_bar_input, c);
+                    app.schedule_search();
+                    StatusBar::Input(sb)
+                }
                 // No-op
                 _ => StatusBar::Input(status_bar_input.clone()),
             }
@@ -0,0 +1,170 @@
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::latex;
+
+/// Text prepared for searching, so that "Sözeri", "S{\"o}zeri" and "sozeri" or "self–report" and
+/// "self-report" are the same.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NormalizedText {
+    pub chars: Vec<char>,
+    /// For every character, the index of the character of the original text it came from,
+    /// so that matches can be highlighted in the original
+    pub origins: Vec<usize>,
+}
+
+impl NormalizedText {
+    pub fn as_string(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn push(&mut self, c: char, origin: usize) {
+        self.chars.push(c);
+        self.origins.push(origin);
+    }
+}
+
+/// Letters that don't decompose into a base letter and accents, and what they are searched as.
+const FOLDED_LETTERS: [(char, &str); 10] = [
+    ('ß', "ss"),
+    ('æ', "ae"),
+    ('œ', "oe"),
+    ('ø', "o"),
+    ('ł', "l"),
+    ('ı', "i"),
+    ('ȷ', "j"),
+    ('đ', "d"),
+    ('ð', "d"),
+    ('þ', "th"),
+];
+
+/// Normalizes text that may contain LaTeX, such as a field value or a search term.
+pub fn normalize_latex(text: &str, stem: bool) -> NormalizedText {
+    normalize(&latex::decode(text), stem)
+}
+
+/// Normalizes (already decoded) text for searching: it is decomposed (NFKD), lowercased, stripped of
+/// accents, and dashes and other punctuation become spaces, except apostrophes, which are left out.
+/// With `stem`, common English suffixes are removed as well, so that "studies" matches "study".
+pub fn normalize(text: &str, stem: bool) -> NormalizedText {
+    let mut normalized = NormalizedText {
+        chars: Vec::with_capacity(text.len()),
+        origins: Vec::with_capacity(text.len()),
+    };
+    for (origin, c) in text.chars().enumerate() {
+        // Most text is plain ASCII, which doesn't need to be decomposed
+        if c.is_ascii() {
+            match c {
+                c if c.is_ascii_alphanumeric() => normalized.push(c.to_ascii_lowercase(), origin),
+                '\'' => {}
+                _ => normalized.push(' ', origin),
+            }
+            continue;
+        }
+        for c in std::iter::once(c).nfkd().flat_map(char::to_lowercase) {
+            if is_combining_mark(c) || matches!(c, '\'' | '’' | 'ʼ') {
+                continue;
+            }
+            match FOLDED_LETTERS.iter().find(|(letter, _)| *letter == c) {
+                Some((_, folded)) => folded.chars().for_each(|c| normalized.push(c, origin)),
+                None if c.is_alphanumeric() => normalized.push(c, origin),
+                None => normalized.push(' ', origin),
+            }
+        }
+    }
+
+    match stem {
+        true => stem_words(normalized),
+        false => normalized,
+    }
+}
+
+/// Suffixes that are replaced when stemming, longest first.
+const SUFFIXES: [(&str, &str); 6] = [
+    ("sses", "ss"),
+    ("ies", "y"),
+    ("ing", ""),
+    ("ed", ""),
+    ("ly", ""),
+    ("s", ""),
+];
+
+/// A light stemmer for English: every word loses the first of `SUFFIXES` it ends with, as long as at
+/// least three letters are left.
+fn stem_words(text: NormalizedText) -> NormalizedText {
+    let mut stemmed = NormalizedText::default();
+    let mut start = 0;
+    while start < text.chars.len() {
+        let length = text.chars[start..]
+            .iter()
+            .position(|c| c.is_alphanumeric() != text.chars[start].is_alphanumeric())
+            .unwrap_or(text.chars.len() - start);
+        let end = start + length;
+        let word = &text.chars[start..end];
+
+        let suffix = SUFFIXES.iter().find(|(suffix, _)| {
+            let suffix_length = suffix.chars().count();
+            word.len() >= suffix_length + 3
+                && word[word.len() - suffix_length..]
+                    .iter()
+                    .copied()
+                    .eq(suffix.chars())
+                // Words like "class" or "analysis" are not plurals
+                && !(*suffix == "s" && matches!(word[word.len() - 2], 's' | 'u' | 'i'))
+        });
+        let kept = match suffix {
+            Some((suffix, _)) => end - suffix.chars().count(),
+            None => end,
+        };
+        for i in start..kept {
+            stemmed.push(text.chars[i], text.origins[i]);
+        }
+        if let Some((_, replacement)) = suffix {
+            replacement
+                .chars()
+                .for_each(|c| stemmed.push(c, text.origins[kept]));
+        }
+
+        start = end;
+    }
+
+    stemmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let normalized = |text: &str| normalize_latex(text, false).as_string();
+
+        assert_eq!("sozeri", normalized("Sözeri"));
+        assert_eq!("sozeri", normalized("S{\\\"o}zeri"));
+        assert_eq!("fernandez", normalized("Fern\\'andez"));
+        assert_eq!("istanbul", normalized("İstanbul"));
+        assert_eq!("istanbul", normalized("ıstanbul"));
+        assert_eq!("strasse", normalized("Straße"));
+        assert_eq!("self report", normalized("self--report"));
+        assert_eq!("self report", normalized("self‐report"));
+        assert_eq!("obrien", normalized("O'Brien"));
+        assert_eq!("fit", normalized("ﬁt"));
+
+        // Every character points back to where it came from
+        let text = normalize("Straße–Ö", false);
+        assert_eq!(vec![0, 1, 2, 3, 4, 4, 5, 6, 7], text.origins);
+    }
+
+    #[test]
+    fn test_stemming() {
+        let stemmed = |text: &str| normalize(text, true).as_string();
+
+        assert_eq!("study study", stemmed("studies study"));
+        assert_eq!("polarization", stemmed("polarizations"));
+        assert_eq!("learn model", stemmed("learning models"));
+        assert_eq!("class analysis bus", stemmed("class analysis bus"));
+        assert_eq!("dress", stemmed("dresses"));
+
+        let text = normalize("studies", true);
+        assert_eq!(vec![0, 1, 2, 3, 4], text.origins);
+    }
+}
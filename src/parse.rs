@@ -1,60 +1,749 @@
-use std::collections::HashMap;
-
-use crate::reference::Reference;
-
-pub fn parse_bibtex(bibtex: String) -> Result<Vec<Reference>, String> {
-    let mut references: Vec<Reference> = Vec::new();
-    let mut fields: HashMap<String, String> = HashMap::new();
-    let mut key: String = String::new();
-
-    for line in bibtex.lines() {
-        // Skip blank lines
-        if line.trim().is_empty() {
-            continue;
-        }
-        // If the line starts with '@', we're parsing a key
-        if line.starts_with('@') {
-            key = line.split('{').collect::<Vec<&str>>()[1]
-                .split(',')
-                .collect::<Vec<&str>>()[0]
-                .to_string();
-        // If the line starts with '}', we're done parsing this reference and can move on
-        } else if line.starts_with('}') {
-            let reference = Reference::new(key.clone(), fields.clone());
-            references.push(reference);
-            fields.clear();
-        // Otherwise, we're parsing a field, which is the form "author = {hello}"
+use std::{collections::HashMap, error::Error, fmt};
+
+use crate::{
+    fields::{Field, Fields},
+    reference::Reference,
+};
+
+/// The contents of a parsed BibTeX file.
+#[derive(Debug, Default)]
+pub struct Bibliography {
+    pub references: Vec<Reference>,
+    /// Macros defined with `@string`, as (name, unexpanded value) pairs in the order they appear in the file
+    pub strings: Vec<(String, String)>,
+    /// The unexpanded values of all `@preamble` blocks
+    pub preambles: Vec<String>,
+    /// The contents of all `@comment` blocks
+    pub comments: Vec<String>,
+    /// Entries that could not be parsed and were skipped
+    pub errors: Vec<ParseError>,
+    /// The source text of the file, split into blocks so it can be written back exactly as it was
+    pub blocks: Vec<Block>,
+}
+
+/// A piece of the source text of a BibTeX file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    /// The source text of an entry that was parsed into the reference whose [`Reference::origin`] is the index of this block
+    Entry(String),
+    /// Anything that is not a reference: text between entries, `@string`, `@preamble` and `@comment` blocks,
+    /// and entries that could not be parsed
+    Verbatim(String),
+}
+
+/// A problem in a BibTeX file, pointing to where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub file: String,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number, counted in characters
+    pub column: usize,
+    /// The line of the file on which the problem was found
+    pub snippet: String,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )?;
+        writeln!(f, "    {}", self.snippet)?;
+        write!(f, "    {:>width$}", "^", width = self.column)
+    }
+}
+
+impl Error for ParseError {}
+
+/// The built-in month macros, which are available in every file.
+const MONTHS: [(&str, &str); 12] = [
+    ("jan", "January"),
+    ("feb", "February"),
+    ("mar", "March"),
+    ("apr", "April"),
+    ("may", "May"),
+    ("jun", "June"),
+    ("jul", "July"),
+    ("aug", "August"),
+    ("sep", "September"),
+    ("oct", "October"),
+    ("nov", "November"),
+    ("dec", "December"),
+];
+
+/// Parses a BibTeX file into a list of references.
+///
+/// Entries may be laid out in any way: fields can span several lines, share a line, or be
+/// delimited by braces (`{...}`), double quotes (`"..."`) or be bare numbers or identifiers.
+/// Values can be concatenated with `#`, and braces inside values may be nested arbitrarily.
+/// Any text outside of an entry is ignored, as BibTeX itself does.
+///
+/// `@string` macros (and the built-in month macros) are expanded in the field values of a reference,
+/// while the unexpanded values are kept in [`Field::raw_value`].
+///
+/// Parsing never fails as a whole: a malformed entry is recorded in [`Bibliography::errors`] and skipped,
+/// and parsing resumes at the next line that starts with '@'. `file` is only used in error messages.
+///
+/// Every byte of the input ends up in exactly one of [`Bibliography::blocks`], so that concatenating
+/// the blocks gives back the original file.
+pub fn parse_bibtex(bibtex: String, file: &str) -> Bibliography {
+    let mut parser = Parser::new(&bibtex, file);
+    let mut bibliography = Bibliography::default();
+    let mut block_start = 0;
+
+    while parser.skip_to_entry() {
+        // skip_to_entry consumed the '@', which belongs to the entry
+        let entry_start = parser.position - 1;
+        push_verbatim(&mut bibliography.blocks, &bibtex[block_start..entry_start]);
+
+        let reference_count = bibliography.references.len();
+        if let Err(error) = parser.parse_block(&mut bibliography) {
+            bibliography.errors.push(error);
+            parser.recover();
+        }
+
+        let entry_text = &bibtex[entry_start..parser.position];
+        if bibliography.references.len() > reference_count {
+            if let Some(reference) = bibliography.references.last_mut() {
+                reference.origin = Some(bibliography.blocks.len());
+            }
+            bibliography
+                .blocks
+                .push(Block::Entry(entry_text.to_string()));
         } else {
-            let field = line.split('=').collect::<Vec<&str>>();
-            // If splitting on '=' failed, skip this field
-            if field.len() < 2 {
-                continue;
+            push_verbatim(&mut bibliography.blocks, entry_text);
+        }
+        block_start = parser.position;
+    }
+
+    push_verbatim(&mut bibliography.blocks, &bibtex[block_start..]);
+
+    bibliography
+}
+
+/// Expands a field value the way it would be expanded in a file without `@string` macros, where only the
+/// built-in month macros are defined. Returns `None` if it isn't a valid value.
+pub fn expand_builtin_macros(raw_value: &str) -> Option<String> {
+    let mut parser = Parser::new(raw_value, "");
+    let (value, _) = parser.parse_value().ok()?;
+    (parser.position == raw_value.len()).then_some(value)
+}
+
+/// Adds verbatim text to the blocks, merging it with the previous block if that is verbatim text as well.
+fn push_verbatim(blocks: &mut Vec<Block>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match blocks.last_mut() {
+        Some(Block::Verbatim(previous)) => previous.push_str(text),
+        _ => blocks.push(Block::Verbatim(text.to_string())),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    file: &'a str,
+    // Byte offset of the next character to be read
+    position: usize,
+    // Macros defined so far, keyed by their lowercased name
+    macros: HashMap<String, String>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str, file: &'a str) -> Self {
+        let macros = MONTHS
+            .iter()
+            .map(|(name, month)| (name.to_string(), month.to_string()))
+            .collect();
+
+        Self {
+            input,
+            file,
+            position: 0,
+            macros,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.position += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", expected)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    /// Skips over everything up to and including the next '@'.
+    /// Returns `false` if the end of the input was reached instead.
+    fn skip_to_entry(&mut self) -> bool {
+        while let Some(c) = self.bump() {
+            if c == '@' {
+                return true;
             }
-            let key = field[0].trim().to_string();
-            let value = strip_optional_suffix(
-                strip_optional_suffix(strip_optional_prefix(field[1].trim(), "{"), ","),
-                "}",
-            )
-            .to_string();
-            fields.insert(key, value);
         }
+        false
     }
 
-    Ok(references)
+    /// Parses a single `@type{...}` block into the bibliography, assuming the '@' has already been consumed.
+    fn parse_block(&mut self, bibliography: &mut Bibliography) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        let entry_type = self.parse_identifier()?;
+        self.skip_whitespace();
+
+        match entry_type.to_lowercase().as_str() {
+            "comment" => bibliography.comments.push(self.parse_comment()?),
+            "preamble" => bibliography.preambles.push(self.parse_preamble()?),
+            "string" => bibliography.strings.push(self.parse_string()?),
+            _ => bibliography.references.push(self.parse_entry(entry_type)?),
+        }
+
+        Ok(())
+    }
+
+    /// Skips ahead to the next '@' that begins a line (ignoring indentation),
+    /// which is most likely the start of the next entry after a malformed one.
+    fn recover(&mut self) {
+        loop {
+            let line_start = self.input[..self.position]
+                .rfind('\n')
+                .map_or(0, |index| index + 1);
+            let at_line_start = self.input[line_start..self.position].trim().is_empty();
+            if at_line_start && self.peek() == Some('@') {
+                return;
+            }
+            if self.bump().is_none() {
+                return;
+            }
+        }
+    }
+
+    /// Parses an opening '{' or '(' and returns the matching closing delimiter.
+    fn parse_opening_delimiter(&mut self) -> Result<char, ParseError> {
+        match self.bump() {
+            Some('{') => Ok('}'),
+            Some('(') => Ok(')'),
+            _ => Err(self.error("expected '{' or '(' after the entry type")),
+        }
+    }
+
+    /// Parses the body of an entry of the form `@type{key, field = value, ...}`,
+    /// assuming everything up to and including the entry type has already been consumed.
+    /// Entries may also be delimited by parentheses instead of braces.
+    fn parse_entry(&mut self, entry_type: &str) -> Result<Reference, ParseError> {
+        let closing = self.parse_opening_delimiter()?;
+
+        self.skip_whitespace();
+        let key = self.parse_key(closing).to_string();
+        let mut fields = Fields::new();
+
+        loop {
+            self.skip_whitespace();
+            if self.eat(closing) {
+                break;
+            }
+            if !self.eat(',') {
+                return Err(self.error(&format!("expected ',' or '{}'", closing)));
+            }
+            self.skip_whitespace();
+            // A trailing comma after the last field is allowed
+            if self.eat(closing) {
+                break;
+            }
+
+            let name = self.parse_identifier()?.to_string();
+            self.skip_whitespace();
+            self.expect('=')?;
+            self.skip_whitespace();
+            let (value, raw_value) = self.parse_value()?;
+            fields.insert(Field {
+                name,
+                value,
+                raw_value: Some(raw_value.to_string()),
+            });
+        }
+
+        Ok(Reference::new(entry_type.to_string(), key, fields))
+    }
+
+    /// Parses the body of a `@string{name = value}` block and defines the macro for the rest of the file.
+    /// Returns the name and the unexpanded value.
+    fn parse_string(&mut self) -> Result<(String, String), ParseError> {
+        let closing = self.parse_opening_delimiter()?;
+        self.skip_whitespace();
+        let name = self.parse_identifier()?.to_string();
+        self.skip_whitespace();
+        self.expect('=')?;
+        self.skip_whitespace();
+        let (value, raw_value) = self.parse_value()?;
+        self.skip_whitespace();
+        self.expect(closing)?;
+
+        self.macros.insert(name.to_lowercase(), value);
+        Ok((name, raw_value.to_string()))
+    }
+
+    /// Parses the body of a `@preamble{value}` block and returns the unexpanded value.
+    fn parse_preamble(&mut self) -> Result<String, ParseError> {
+        let closing = self.parse_opening_delimiter()?;
+        self.skip_whitespace();
+        let (_, raw_value) = self.parse_value()?;
+        self.skip_whitespace();
+        self.expect(closing)?;
+
+        Ok(raw_value.to_string())
+    }
+
+    /// Parses the body of a `@comment`, which is either delimited like an entry or runs until the end of the line.
+    fn parse_comment(&mut self) -> Result<String, ParseError> {
+        let comment = match self.peek() {
+            Some('{') => {
+                self.bump();
+                self.parse_delimited('}')?
+            }
+            Some('(') => {
+                self.bump();
+                self.parse_delimited(')')?
+            }
+            _ => {
+                let start = self.position;
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.bump();
+                }
+                &self.input[start..self.position]
+            }
+        };
+
+        Ok(comment.trim().to_string())
+    }
+
+    /// Parses a citation key, which runs up to the first comma, whitespace or closing delimiter.
+    fn parse_key(&mut self, closing: char) -> &'a str {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c != ',' && c != closing && !c.is_whitespace())
+        {
+            self.bump();
+        }
+        &self.input[start..self.position]
+    }
+
+    /// Parses an entry type, field name or macro name.
+    fn parse_identifier(&mut self) -> Result<&'a str, ParseError> {
+        let start = self.position;
+        while self.peek().is_some_and(is_identifier_char) {
+            self.bump();
+        }
+        if start == self.position {
+            Err(self.error("expected an identifier"))
+        } else {
+            Ok(&self.input[start..self.position])
+        }
+    }
+
+    /// Parses a field value, which consists of one or more parts joined by '#'.
+    /// Returns the value with all macros expanded, as well as the unexpanded source text of the value.
+    fn parse_value(&mut self) -> Result<(String, &'a str), ParseError> {
+        let start = self.position;
+        let mut end;
+        let mut value = String::new();
+
+        loop {
+            match self.peek() {
+                Some('{') => {
+                    self.bump();
+                    value.push_str(self.parse_delimited('}')?);
+                }
+                Some('"') => {
+                    self.bump();
+                    value.push_str(self.parse_delimited('"')?);
+                }
+                Some(c) if c.is_ascii_digit() => {
+                    let start = self.position;
+                    while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        self.bump();
+                    }
+                    value.push_str(&self.input[start..self.position]);
+                }
+                // Bare words are macros (`month = jun`). Undefined macros are kept as they are.
+                Some(c) if is_identifier_char(c) => {
+                    let name = self.parse_identifier()?;
+                    match self.macros.get(&name.to_lowercase()) {
+                        Some(expansion) => value.push_str(expansion),
+                        None => value.push_str(name),
+                    }
+                }
+                _ => return Err(self.error("expected a field value")),
+            }
+
+            end = self.position;
+            self.skip_whitespace();
+            if !self.eat('#') {
+                break;
+            }
+            self.skip_whitespace();
+        }
+
+        Ok((collapse_whitespace(&value), &self.input[start..end]))
+    }
+
+    /// Parses the contents of a braced or quoted value up to the (unnested) closing delimiter,
+    /// assuming the opening delimiter has already been consumed.
+    /// Nested braces are kept in the result; a '"' only closes a quoted value at brace depth 0.
+    fn parse_delimited(&mut self, closing: char) -> Result<&'a str, ParseError> {
+        let start = self.position;
+        let mut depth: usize = 0;
+
+        loop {
+            let end = self.position;
+            match self.bump() {
+                Some(c) if c == closing && depth == 0 => return Ok(&self.input[start..end]),
+                Some('{') => depth += 1,
+                Some('}') => {
+                    if depth == 0 {
+                        return Err(self.error("unbalanced '}' in field value"));
+                    }
+                    depth -= 1;
+                }
+                // A backslash escapes the next character, so `\"` does not end a quoted value.
+                // Braces are always counted, because BibTeX requires them to be balanced.
+                Some('\\') => {
+                    if self.peek().is_some_and(|c| c != '{' && c != '}') {
+                        self.bump();
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    self.position = start;
+                    return Err(
+                        self.error(&format!("unterminated field value, expected '{}'", closing))
+                    );
+                }
+            }
+        }
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        let line_start = self.input[..self.position]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        let line_end = self.input[self.position..]
+            .find('\n')
+            .map_or(self.input.len(), |index| self.position + index);
+
+        ParseError {
+            file: self.file.to_string(),
+            line: self.input[..self.position].matches('\n').count() + 1,
+            column: self.input[line_start..self.position].chars().count() + 1,
+            snippet: self.input[line_start..line_end].trim_end().to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Characters that can appear in entry types, field names and macro names.
+fn is_identifier_char(c: char) -> bool {
+    !c.is_whitespace()
+        && !matches!(
+            c,
+            '"' | '#' | '%' | '\'' | '(' | ')' | ',' | '=' | '@' | '{' | '}'
+        )
 }
 
-fn strip_optional_prefix<'a>(s: &'a str, prefix: &str) -> &'a str {
-    if let Some(s1) = s.strip_prefix(prefix) {
-        s1
-    } else {
-        s
+/// Replaces every run of whitespace (including newlines) with a single space, as BibTeX does.
+fn collapse_whitespace(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(references: &'a [Reference], key: &str) -> &'a Reference {
+        references
+            .iter()
+            .find(|reference| reference.key == key)
+            .unwrap_or_else(|| panic!("No reference with key {}", key))
+    }
+
+    fn field<'a>(reference: &'a Reference, name: &str) -> &'a str {
+        reference
+            .fields
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_parse_test_bibliography() {
+        let bibtex = include_str!("../test_bibliography.bib").to_string();
+        let bibliography = parse_bibtex(bibtex, "test_bibliography.bib");
+        assert!(bibliography.errors.is_empty());
+        let references = bibliography.references;
+
+        assert_eq!(283, references.len());
+        assert!(references.iter().all(|reference| !reference.key.is_empty()));
+        assert_eq!(
+            34,
+            references
+                .iter()
+                .filter(|reference| reference.entry_type() == "book")
+                .count()
+        );
+
+        let veerman = find(&references, "Veerman2021");
+        assert_eq!(
+            "Gert Jan Veerman and Lucinda Platt",
+            field(veerman, "author")
+        );
+        assert_eq!("2021", field(veerman, "year"));
+        assert_eq!("106--125", field(veerman, "pages"));
+        assert_eq!(12, veerman.fields.iter().count());
+    }
+
+    #[test]
+    fn test_parse_reference_manager_exports() {
+        let bibtex = include_str!("../test_bibliography_exports.bib").to_string();
+        let bibliography = parse_bibtex(bibtex, "test_bibliography_exports.bib");
+        assert!(bibliography.errors.is_empty());
+        let references = bibliography.references;
+
+        assert_eq!(6, references.len());
+
+        let zotero = find(&references, "smith_deep_2019");
+        assert_eq!("Deep learning for {NASA} missions", field(zotero, "title"));
+        assert_eq!(
+            "This abstract spans several lines, contains an = sign and {nested {braces}}.",
+            field(zotero, "abstract")
+        );
+        assert_eq!(
+            "https://example.org/article?id=42&format=pdf",
+            field(zotero, "url")
+        );
+        assert_eq!("January", field(zotero, "month"));
+        assert_eq!(16, zotero.fields.iter().count());
+
+        let jabref = find(&references, "Garcia2020");
+        assert_eq!(
+            "Garc{\\'i}a, Mar{\\'i}a and M{\\\"u}ller, Hans",
+            field(jabref, "author")
+        );
+        assert_eq!(
+            "{Quantum} effects in $\\alpha$-decay",
+            field(jabref, "title")
+        );
+
+        let lee = find(&references, "Lee2015");
+        assert_eq!("Parsing {BibTeX} {{Correctly}}", field(lee, "title"));
+        assert_eq!("inproceedings", lee.entry_type());
+        assert!(lee.to_bibtex().starts_with("@InProceedings{Lee2015,\n"));
+
+        let mendeley = find(&references, "Doe2018");
+        assert_eq!("{A study of \"quoted\" things}", field(mendeley, "title"));
+        assert_eq!(
+            ":C$\\backslash$:/Users/jane/Mendeley/Doe2018.pdf:pdf",
+            field(mendeley, "file")
+        );
+        assert_eq!("Thesis", field(mendeley, "mendeley-groups"));
+
+        let brown = find(&references, "Brown1999");
+        assert_eq!("On {\\\"U}bersetzung", field(brown, "title"));
+        assert_eq!("1999", field(brown, "year"));
+        assert_eq!("misc", brown.entry_type());
+        assert_eq!("Part one", field(brown, "note"));
+
+        let black = find(&references, "Black2001");
+        assert_eq!("Compact", field(black, "title"));
+        assert_eq!("2001", field(black, "year"));
+
+        assert_eq!(
+            vec!["jabref-meta: databaseType:bibtex;"],
+            bibliography.comments
+        );
     }
+
+    #[test]
+    fn test_parse_macros() {
+        let bibtex = String::from(
+            r#"
+            @preamble{ "\newcommand{\noopsort}[1]{}" }
+            @String{jasa = "Journal of the American Statistical Association"}
+            @string(jasa2 = jasa # " (2nd series)")
+            @comment this line is ignored @article{not_an_entry}
+            @Comment{@article{also_not_an_entry, title = {Hidden}}}
+
+            @article{a,
+                journal = JASA,
+                month = dec,
+                note = "Published in " # jasa2 # ", " # jun,
+                series = undefined_macro
+            }
+            "#,
+        );
+        let bibliography = parse_bibtex(bibtex, "macros.bib");
+
+        assert!(bibliography.errors.is_empty());
+        assert_eq!(1, bibliography.references.len());
+        assert_eq!(
+            vec![
+                (
+                    String::from("jasa"),
+                    String::from("\"Journal of the American Statistical Association\"")
+                ),
+                (
+                    String::from("jasa2"),
+                    String::from("jasa # \" (2nd series)\"")
+                ),
+            ],
+            bibliography.strings
+        );
+        assert_eq!(
+            vec!["\"\\newcommand{\\noopsort}[1]{}\""],
+            bibliography.preambles
+        );
+        assert_eq!(2, bibliography.comments.len());
+
+        let reference = &bibliography.references[0];
+        assert_eq!(
+            "Journal of the American Statistical Association",
+            field(reference, "journal")
+        );
+        assert_eq!("December", field(reference, "month"));
+        assert_eq!(
+            "Published in Journal of the American Statistical Association (2nd series), June",
+            field(reference, "note")
+        );
+        assert_eq!("undefined_macro", field(reference, "series"));
+
+        // The unexpanded values are kept, so the entry can be written back the way it was
+        let raw_value = |name| {
+            reference
+                .fields
+                .get_field(name)
+                .and_then(|field: &Field| field.raw_value.as_deref())
+        };
+        assert_eq!(Some("JASA"), raw_value("journal"));
+        assert_eq!(
+            Some("\"Published in \" # jasa2 # \", \" # jun"),
+            raw_value("note")
+        );
+        assert!(reference.to_bibtex().contains("journal = JASA,"));
+
+        // Outside of this file, the macros it defines have to be expanded, but the built-in ones don't
+        let standalone = reference.to_standalone_bibtex();
+        assert!(standalone.contains("journal = {Journal of the American Statistical Association},"));
+        assert!(standalone.contains("month = dec,"));
+        assert!(standalone.contains("series = undefined_macro,"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        {
+            let bibtex = String::from("@article{a,\n  title = {Unterminated {\n}\n");
+            let bibliography = parse_bibtex(bibtex, "unterminated.bib");
+            assert_eq!(
+                vec![ParseError {
+                    file: String::from("unterminated.bib"),
+                    line: 2,
+                    column: 12,
+                    snippet: String::from("  title = {Unterminated {"),
+                    message: String::from("unterminated field value, expected '}'"),
+                }],
+                bibliography.errors
+            );
+            assert_eq!(
+                "unterminated.bib:2:12: unterminated field value, expected '}'\n      title = {Unterminated {\n               ^",
+                bibliography.errors[0].to_string()
+            );
+        }
+        {
+            let bibtex = String::from("@article{a, title {Missing equals sign}}");
+            let bibliography = parse_bibtex(bibtex, "test.bib");
+            assert_eq!(1, bibliography.errors.len());
+            assert_eq!(19, bibliography.errors[0].column);
+        }
+        {
+            let bibtex = String::from("@article a, title = {No opening brace}}");
+            let bibliography = parse_bibtex(bibtex, "test.bib");
+            assert_eq!(1, bibliography.errors.len());
+        }
+        {
+            // The next entry is not read as a field name of an entry that wasn't closed
+            let bibtex = String::from("@article{a, title = {A},\n@book{b, title = {B}}");
+            let bibliography = parse_bibtex(bibtex, "test.bib");
+            assert_eq!(
+                vec![(2, 1, String::from("expected an identifier"))],
+                bibliography
+                    .errors
+                    .iter()
+                    .map(|error| (error.line, error.column, error.message.clone()))
+                    .collect::<Vec<(usize, usize, String)>>()
+            );
+            assert_eq!("b", bibliography.references[0].key);
+        }
+    }
+
+    #[test]
+    fn test_parse_recovers_from_errors() {
+        let bibtex = String::from(
+            "@article{first, title = {First}}
+
+@article{broken,
+  title = {Broken,
+  note = {mail me @ home}
+}
+
+@book{second,
+  title = {Second},
 }
+  @misc{third title = {Missing comma}}
+@misc{fourth, title = {Fourth}}
+",
+        );
+        let bibliography = parse_bibtex(bibtex, "test.bib");
 
-fn strip_optional_suffix<'a>(s: &'a str, suffix: &str) -> &'a str {
-    if let Some(s1) = s.strip_suffix(suffix) {
-        s1
-    } else {
-        s
+        let keys: Vec<&str> = bibliography
+            .references
+            .iter()
+            .map(|reference| reference.key.as_str())
+            .collect();
+        assert_eq!(vec!["first", "second", "fourth"], keys);
+        assert_eq!(
+            // The unterminated title of the broken entry only ends at the closing brace of the entry,
+            // so the problem is found at the start of the next entry
+            vec![8, 11],
+            bibliography
+                .errors
+                .iter()
+                .map(|error| error.line)
+                .collect::<Vec<usize>>()
+        );
     }
 }
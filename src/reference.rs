@@ -1,59 +1,117 @@
-use std::{collections::HashMap, str::Split};
+use crate::{
+    fields::{Field, Fields},
+    latex,
+    normalize::normalize_latex,
+    parse::expand_builtin_macros,
+    sort::CollationKey,
+};
 
 #[derive(Debug, Clone)]
 pub struct Reference {
+    /// The entry type as it appears in the file, e.g. `article` or `InProceedings`
+    pub entry_type: String,
     pub key: String,
-    pub fields: HashMap<String, String>,
+    pub fields: Fields,
+    /// The index of the [`crate::parse::Block`] this reference was parsed from, if it was read from a file
+    pub origin: Option<usize>,
+    /// Whether the reference has been changed since it was read, in which case it can no longer be
+    /// written back using its original source text
+    pub modified: bool,
 }
 
-#[derive(Debug, PartialEq)]
-enum AuthorName {
-    FirstNameLastName {
-        first_name: String,
-        last_name: String,
+/// A name from the `author` or `editor` field, split according to BibTeX's rules for names.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthorName {
+    /// The four parts of a person's name, any of which (but `last`) can be empty.
+    /// For "Ludwig van Beethoven", `von` is "van"; for "King, Jr., Martin Luther", `jr` is "Jr.".
+    Person {
+        first: String,
+        von: String,
+        last: String,
+        jr: String,
     },
+    /// A name that should not be split into parts, like the corporate author `{World Health Organization}`
     FullName(String),
+    /// `and others` at the end of a list of names, meaning "et al."
+    Others,
 }
 
-#[derive(Debug, PartialEq)]
-struct Author {
-    name: AuthorName,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Author {
+    pub name: AuthorName,
 }
 
 impl Author {
-    fn new(first_name: String, last_name: String) -> Self {
-        Self {
-            name: AuthorName::FirstNameLastName {
-                first_name,
-                last_name,
-            },
-        }
-    }
-
-    fn new_from_full_name(full_name: String) -> Self {
-        Self {
-            name: AuthorName::FullName(full_name),
+    /// The full name as "First von Last, Jr", decoded from LaTeX.
+    pub fn full_name(&self) -> String {
+        match self.name {
+            AuthorName::Person {
+                ref first,
+                ref von,
+                ref last,
+                ref jr,
+            } => {
+                let name = [first, von, last]
+                    .iter()
+                    .filter(|part| !part.is_empty())
+                    .map(|part| part.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" ");
+                if jr.is_empty() {
+                    latex::decode(&name)
+                } else {
+                    latex::decode(&format!("{}, {}", name, jr))
+                }
+            }
+            AuthorName::FullName(ref full_name) => latex::decode(full_name),
+            AuthorName::Others => String::from("et al."),
         }
     }
 }
 
 impl Reference {
-    pub fn new(key: String, fields: HashMap<String, String>) -> Reference {
-        Reference { key, fields }
+    pub fn new(entry_type: String, key: String, fields: Fields) -> Reference {
+        Reference {
+            entry_type,
+            key,
+            fields,
+            origin: None,
+            modified: false,
+        }
     }
 
-    pub fn as_array(&self) -> [Option<String>; 4] {
-        let title: Option<String> = self.fields.get("title").cloned();
-        let author: Option<String> = self.formatted_author().to_owned();
-        let year: Option<String> = self.fields.get("year").cloned();
-
-        [Some(self.key.to_owned()), author, year, title]
+    pub fn as_array(&self) -> [Option<String>; 5] {
+        let title: Option<String> = self.decoded_field("title");
+        // Edited volumes often have no authors, in which case we show the editors instead
+        let author: Option<String> = self
+            .formatted_author()
+            .or_else(|| Some(format!("{} (ed.)", self.formatted_editor()?)));
+        let year: Option<String> = self.decoded_field("year");
+
+        [
+            Some(self.key.to_owned()),
+            Some(self.entry_type()),
+            author,
+            year,
+            title,
+        ]
     }
 
     pub fn key(&self) -> &str {
         &self.key
     }
 
+    /// The entry type in lowercase, since BibTeX entry types are case-insensitive
+    pub fn entry_type(&self) -> String {
+        self.entry_type.to_lowercase()
+    }
+
+    /// The value of a field decoded from LaTeX into Unicode, for displaying, sorting and searching.
+    /// The stored value is left untouched.
+    pub fn decoded_field(&self, name: &str) -> Option<String> {
+        self.fields.get(name).map(|value| latex::decode(value))
+    }
+
     pub fn title(&self) -> Option<&String> {
         self.fields.get("title")
     }
@@ -62,19 +120,126 @@ impl Reference {
         self.fields.get("year")
     }
 
-    pub fn formatted_author(&self) -> Option<String> {
+    pub fn authors(&self) -> Vec<Author> {
         self.fields
             .get("author")
-            .map(extract_authors_from_string)
-            .map(|authors| authors.iter().map(format_author).collect::<Vec<String>>())
-            .map(|authors| authors.join("; "))
+            .map(|authors| extract_authors_from_string(authors))
+            .unwrap_or_default()
+    }
+
+    pub fn editors(&self) -> Vec<Author> {
+        self.fields
+            .get("editor")
+            .map(|editors| extract_authors_from_string(editors))
+            .unwrap_or_default()
+    }
+
+    pub fn formatted_author(&self) -> Option<String> {
+        format_authors(&self.authors())
+    }
+
+    pub fn formatted_editor(&self) -> Option<String> {
+        format_authors(&self.editors())
     }
 
+    /// Whether two references are probably the same publication: they have the same DOI, or if either
+    /// has none, the same title and year.
+    pub fn is_duplicate_of(&self, other: &Reference) -> bool {
+        let doi = |reference: &Reference| {
+            let doi = reference.fields.get("doi")?.trim().to_lowercase();
+            let prefixes = [
+                "https://doi.org/",
+                "http://doi.org/",
+                "https://dx.doi.org/",
+                "doi:",
+            ];
+            let doi = prefixes
+                .iter()
+                .find_map(|prefix| doi.strip_prefix(prefix))
+                .unwrap_or(&doi)
+                .to_string();
+            Some(doi)
+        };
+        if let (Some(doi), Some(other_doi)) = (doi(self), doi(other)) {
+            return doi == other_doi;
+        }
+
+        let title = |reference: &Reference| {
+            let title = normalize_latex(reference.title()?, false).as_string();
+            Some(title.split_whitespace().collect::<Vec<&str>>().join(" "))
+        };
+        title(self).is_some() && title(self) == title(other) && self.year() == other.year()
+    }
+
+    /// The keywords, as LaTeX. They are separated by commas, or by semicolons in some files.
+    pub fn keywords(&self) -> Vec<&str> {
+        self.fields
+            .get("keywords")
+            .map(|keywords| {
+                keywords
+                    .split([',', ';'])
+                    .map(str::trim)
+                    .filter(|keyword| !keyword.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Adds a keyword (`add`) or removes it, keeping the separator that the keywords already use.
+    /// Keywords are compared without regard to case. Returns whether the keywords changed.
+    pub fn set_keyword(&mut self, keyword: &str, add: bool) -> bool {
+        let mut keywords: Vec<String> = self.keywords().into_iter().map(String::from).collect();
+        let present = keywords
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(keyword));
+        match (present, add) {
+            (false, true) => keywords.push(keyword.to_string()),
+            (true, false) => keywords.retain(|existing| !existing.eq_ignore_ascii_case(keyword)),
+            _ => return false,
+        }
+        if keywords.is_empty() {
+            self.fields.remove("keywords");
+            return true;
+        }
+        let separator = match self.fields.get("keywords") {
+            Some(value) if value.contains(';') && !value.contains(',') => "; ",
+            _ => ", ",
+        };
+        let value = keywords.join(separator);
+        self.fields.insert(Field {
+            name: String::from("keywords"),
+            raw_value: Some(format!("{{{}}}", value)),
+            value,
+        });
+        true
+    }
+
+    /// Serializes the reference, writing field values the way they appear in the file (with macros unexpanded).
+    /// Values that were not read from a file are encoded from Unicode into LaTeX.
     pub fn to_bibtex(&self) -> String {
-        let mut bibtex = format!("@article{{{key},\n", key = self.key);
+        self.serialize(written_value)
+    }
+
+    /// Serializes the reference so that it can be used in another file, which doesn't define the `@string`
+    /// macros of this one: values that use them are written expanded.
+    pub fn to_standalone_bibtex(&self) -> String {
+        self.serialize(|field| match &field.raw_value {
+            Some(raw_value) if expand_builtin_macros(raw_value).as_ref() != Some(&field.value) => {
+                format!("{{{}}}", field.value)
+            }
+            _ => written_value(field),
+        })
+    }
 
-        for (field, value) in &self.fields {
-            bibtex.push_str(&format!("    {} = {{{}}},\n", field, value));
+    fn serialize(&self, value: impl Fn(&Field) -> String) -> String {
+        let mut bibtex = format!(
+            "@{entry_type}{{{key},\n",
+            entry_type = self.entry_type,
+            key = self.key
+        );
+
+        for field in self.fields.iter() {
+            bibtex.push_str(&format!("    {} = {},\n", field.name, value(field)));
         }
 
         bibtex.push_str("}\n");
@@ -93,118 +258,296 @@ impl Eq for Reference {}
 
 impl PartialOrd for Reference {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let a1 = self.fields.get("author");
-        let a2 = other.fields.get("author");
-        a1.partial_cmp(&a2)
+        Some(self.cmp(other))
     }
 }
 
+/// References are ordered by their [`CollationKey`]. References that collate the same but are not equal
+/// are ordered by their fields, so that only equal references compare as equal.
 impl Ord for Reference {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        let a1 = self.fields.get("author");
-        let a2 = other.fields.get("author");
-        a1.cmp(&a2)
+        fn parts(field: &Field) -> (&String, &String, &Option<String>) {
+            (&field.name, &field.value, &field.raw_value)
+        }
+        CollationKey::new(self)
+            .cmp(&CollationKey::new(other))
+            .then_with(|| {
+                self.fields
+                    .iter()
+                    .map(parts)
+                    .cmp(other.fields.iter().map(parts))
+            })
     }
 }
 
-fn extract_authors_from_string(authors: &String) -> Vec<Author> {
-    return authors
-        .split(" and ")
-        .map(|author| author.split(","))
-        .map(parse_split_author)
-        .collect();
+/// Fields whose values are not LaTeX but are used as they are, like URLs, which can contain `_` and `%`
+const VERBATIM_FIELDS: [&str; 4] = ["url", "doi", "file", "eprint"];
 
-    fn parse_split_author(lastname_firstname: Split<&str>) -> Author {
-        let vec = lastname_firstname.collect::<Vec<&str>>();
-        // If, when we split this author on ',' and `collect()` the results, we have exactly 2 elements,
-        // that means we have a last name and a first name. In that case, we simply return those as a new author.
-        // ("Doe, John")
-        if vec.len() == 2 {
-            let last_name = vec[0].trim().to_owned();
-            let first_name = vec[1].trim().to_owned();
-            Author {
-                name: AuthorName::FirstNameLastName {
-                    first_name,
-                    last_name,
-                },
+/// How a field is written to a file: as it was read, or encoded from Unicode into LaTeX.
+fn written_value(field: &Field) -> String {
+    match &field.raw_value {
+        Some(raw_value) => raw_value.clone(),
+        None if VERBATIM_FIELDS
+            .iter()
+            .any(|name| field.name.eq_ignore_ascii_case(name)) =>
+        {
+            format!("{{{}}}", field.value)
+        }
+        None => format!("{{{}}}", latex::encode(&field.value)),
+    }
+}
+
+/// Parses a list of names as BibTeX does: names are separated by "and" (in any case, and not inside braces),
+/// and each name is of the form "First von Last", "von Last, First" or "von Last, Jr, First".
+fn extract_authors_from_string(authors: &str) -> Vec<Author> {
+    let mut names: Vec<Vec<NameToken>> = vec![Vec::new()];
+
+    for token in tokenize_names(authors) {
+        match token {
+            NameToken::Word(ref word) if word.eq_ignore_ascii_case("and") => names.push(Vec::new()),
+            token => {
+                if let Some(name) = names.last_mut() {
+                    name.push(token);
+                }
             }
-        } else {
-            // If not, we proceed by checking if the full name is of the format "John Doe" or "Marieke M.A. Hendriksen" by splitting on whitespace.
-            let names: Vec<&str> = vec[0].split_ascii_whitespace().collect();
-
-            // John Doe
-            // Marieke M.A. Huuu
-            if names.len() > 2 && is_initials(names[1]) {
-                let first_name = names[..names.len() - 1].join(" ");
-                let last_name = names[names.len() - 1].to_owned();
-                return Author::new(first_name, last_name);
+        }
+    }
+
+    names
+        .iter()
+        .filter(|name| !name.is_empty())
+        .map(|name| parse_name(name))
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum NameToken {
+    Word(String),
+    Comma,
+}
+
+/// Splits a list of names into words and commas. Whitespace, '~' and commas inside braces don't split words.
+fn tokenize_names(names: &str) -> Vec<NameToken> {
+    let mut tokens: Vec<NameToken> = Vec::new();
+    let mut word = String::new();
+    let mut depth: usize = 0;
+
+    for c in names.chars() {
+        match c {
+            '{' => {
+                depth += 1;
+                word.push(c);
+            }
+            '}' => {
+                depth = depth.saturating_sub(1);
+                word.push(c);
             }
+            ',' if depth == 0 => {
+                if !word.is_empty() {
+                    tokens.push(NameToken::Word(std::mem::take(&mut word)));
+                }
+                tokens.push(NameToken::Comma);
+            }
+            c if depth == 0 && (c.is_whitespace() || c == '~') => {
+                if !word.is_empty() {
+                    tokens.push(NameToken::Word(std::mem::take(&mut word)));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(NameToken::Word(word));
+    }
 
-            if names.len() == 2 {
-                // If so, we assume "Doe" is the last name and "John" is the first name.
-                Author::new(names[0].to_string(), names[1].to_string())
-            } else {
-                // If the full name is not of the format "John Doe", we fall back on returning an AuthorName::FullName.
-                Author::new_from_full_name(vec[0].to_string())
+    tokens
+}
+
+fn parse_name(tokens: &[NameToken]) -> Author {
+    // Split the name into its comma-separated parts, each consisting of a list of words
+    let parts: Vec<Vec<&str>> = tokens
+        .split(|token| *token == NameToken::Comma)
+        .map(|part| {
+            part.iter()
+                .filter_map(|token| match token {
+                    NameToken::Word(word) => Some(word.as_str()),
+                    NameToken::Comma => None,
+                })
+                .collect()
+        })
+        .collect();
+
+    let name = match parts.as_slice() {
+        [words] if words.len() == 1 && words[0].eq_ignore_ascii_case("others") => {
+            AuthorName::Others
+        }
+        // A name that is entirely enclosed in braces is not split up
+        [words] if words.len() == 1 && braced_group_len(words[0]) == Some(words[0].len()) => {
+            AuthorName::FullName(words[0][1..words[0].len() - 1].to_string())
+        }
+        // First von Last
+        [words] => {
+            let last_word = words.len() - 1;
+            match words[..last_word].iter().position(|word| is_von(word)) {
+                Some(von_start) => {
+                    let (von, last) = split_von_last(&words[von_start..]);
+                    person(&words[..von_start], von, last, &[])
+                }
+                None => person(&words[..last_word], &[], &words[last_word..], &[]),
             }
         }
+        // von Last, First
+        [von_last, first] => {
+            let (von, last) = split_von_last(von_last);
+            person(first, von, last, &[])
+        }
+        // von Last, Jr, First (any further commas are treated as part of the first name)
+        [von_last, jr, first @ ..] => {
+            let (von, last) = split_von_last(von_last);
+            person(&first.concat(), von, last, jr)
+        }
+        [] => AuthorName::FullName(String::new()),
+    };
+
+    Author { name }
+}
+
+fn person(first: &[&str], von: &[&str], last: &[&str], jr: &[&str]) -> AuthorName {
+    AuthorName::Person {
+        first: first.join(" "),
+        von: von.join(" "),
+        last: last.join(" "),
+        jr: jr.join(" "),
     }
 }
 
-fn is_initials(str: &str) -> bool {
-    if str == "" {
-        false
+/// Splits "von Last" into its parts: the von part runs up to the last lowercase word,
+/// but the last word of the name always belongs to the last name.
+fn split_von_last<'a, 'b>(words: &'b [&'a str]) -> (&'b [&'a str], &'b [&'a str]) {
+    let last_word = words.len().saturating_sub(1);
+    match words[..last_word].iter().rposition(|word| is_von(word)) {
+        Some(von_end) => words.split_at(von_end + 1),
+        None => words.split_at(0),
     }
-    // If all characters are uppercase letters, we can assume this is an initial
-    else if str.chars().all(|c| c.is_alphabetic() && c.is_uppercase()) {
-        true
-    } else {
-        // If not, we check if the string is of the form "M.A.B." by looping over it
-        for (i, c) in str.char_indices() {
-            // The current character should be a letter for even indices, and a period ('.') for odd indices.
-            let is_expected_initial_char = if i % 2 == 0 {
-                c.is_alphabetic()
-            } else {
-                c == '.'
-            };
+}
+
+/// LaTeX commands that put an accent on the letter that follows them, like `\"o` or `\c{c}`
+const ACCENT_COMMANDS: [&str; 17] = [
+    "`", "'", "^", "\"", "~", "=", ".", "u", "v", "H", "t", "c", "d", "b", "k", "r", "G",
+];
 
-            if !is_expected_initial_char {
-                return false;
+/// Whether a word is part of the von part of a name ("van", "de la"), which is the case if it starts with
+/// a lowercase letter. As in BibTeX, braced groups are skipped (so `{Van}` is not a von word), except for
+/// special characters like `{\"o}` or `{\ss}`, which count as the letter they represent.
+fn is_von(word: &str) -> bool {
+    let mut rest = word;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' {
+            return command_is_lowercase(&rest[1..]).unwrap_or(false);
+        }
+        if c == '{' {
+            if rest[1..].starts_with('\\') {
+                return command_is_lowercase(&rest[2..]).unwrap_or(false);
             }
+            rest = &rest[braced_group_len(rest).unwrap_or(rest.len())..];
+            continue;
         }
-        true
+        if c.is_alphabetic() {
+            return c.is_lowercase();
+        }
+        rest = &rest[c.len_utf8()..];
     }
+
+    false
 }
 
-pub fn _search_references<'a>(
-    references: &'a Vec<Reference>,
-    search_string: &'a String,
-) -> Vec<&'a Reference> {
-    references
-        .iter()
-        .filter(|reference| _contains_string(reference, search_string))
-        .collect()
+/// Determines whether the letter produced by a LaTeX command (given without its backslash) is lowercase:
+/// for accents that is the accented letter (`"o`, `c{C}`), otherwise the command itself (`ss`, `OE`).
+fn command_is_lowercase(command: &str) -> Option<bool> {
+    let first = command.chars().next()?;
+    let name_len = if first.is_ascii_alphabetic() {
+        command
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(command.len())
+    } else {
+        first.len_utf8()
+    };
+    let name = &command[..name_len];
+
+    let letter = if ACCENT_COMMANDS.contains(&name) {
+        command[name_len..].chars().find(|c| c.is_alphabetic())?
+    } else {
+        name.chars().next().filter(|c| c.is_alphabetic())?
+    };
+    Some(letter.is_lowercase())
+}
+
+/// The length in bytes of the braced group at the start of `s`, including both braces,
+/// or `None` if `s` does not start with a (closed) braced group.
+fn braced_group_len(s: &str) -> Option<usize> {
+    if !s.starts_with('{') {
+        return None;
+    }
+    let mut depth: usize = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
 }
 
-fn _contains_string(reference: &Reference, string: &String) -> bool {
-    reference.key.contains(string)
-        || reference
-            .fields
-            .values()
-            .any(|value| value.contains(string))
+fn format_authors(authors: &[Author]) -> Option<String> {
+    if authors.is_empty() {
+        return None;
+    }
+    Some(
+        authors
+            .iter()
+            .map(format_author)
+            .collect::<Vec<String>>()
+            .join("; "),
+    )
 }
 
+/// Formats a name for display as "von Last, Jr, F", decoding any LaTeX in it.
 fn format_author(author: &Author) -> String {
     match author.name {
-        AuthorName::FirstNameLastName {
-            ref first_name,
-            ref last_name,
-        } => format!(
-            "{}, {}",
-            last_name,
-            first_name.chars().next().unwrap_or_default()
-        ),
-        AuthorName::FullName(ref full_name) => full_name.to_string(),
+        AuthorName::Person {
+            ref first,
+            ref von,
+            ref last,
+            ref jr,
+        } => {
+            let (first, von, last, jr) = (
+                latex::decode(first),
+                latex::decode(von),
+                latex::decode(last),
+                latex::decode(jr),
+            );
+            let mut formatted = if von.is_empty() {
+                last.to_string()
+            } else {
+                format!("{} {}", von, last)
+            };
+            if !jr.is_empty() {
+                formatted = format!("{}, {}", formatted, jr);
+            }
+            if let Some(initial) = first.chars().next() {
+                formatted = format!("{}, {}", formatted, initial);
+            }
+            formatted
+        }
+        AuthorName::FullName(ref full_name) => latex::decode(full_name),
+        AuthorName::Others => String::from("et al."),
     }
 }
 
@@ -212,47 +555,20 @@ fn format_author(author: &Author) -> String {
 mod tests {
     use super::*;
 
-    use crate::reference::Reference;
-    use std::collections::HashMap;
-
-    fn _example_references() -> [Reference; 2] {
-        let mut fields1: HashMap<String, String> = HashMap::new();
-        let mut fields2: HashMap<String, String> = HashMap::new();
-
-        fields1.insert(String::from("author"), String::from("Smith, John"));
-        fields1.insert(String::from("title"), String::from("Programming Language"));
-        fields1.insert(String::from("year"), String::from("2021"));
-        fields1.insert(String::from("journal"), String::from("Rust Journal"));
-        fields1.insert(String::from("volume"), String::from("5"));
-        fields1.insert(String::from("number"), String::from("2"));
-        fields1.insert(String::from("pages"), String::from("100-120"));
-        fields1.insert(String::from("month"), String::from("July"));
-        fields1.insert(String::from("note"), String::from("a sample reference."));
-
-        fields2.insert(String::from("author"), String::from("Doe, Jane"));
-        fields2.insert(String::from("title"), String::from("Introduction to Rust"));
-        fields2.insert(String::from("year"), String::from("2022"));
-        fields2.insert(String::from("journal"), String::from("Rust Gazette"));
-        fields2.insert(String::from("volume"), String::from("7"));
-        fields2.insert(String::from("number"), String::from("1"));
-        fields2.insert(String::from("pages"), String::from("50-70"));
-        fields2.insert(String::from("month"), String::from("January"));
-        fields2.insert(
-            String::from("note"),
-            String::from("Another sample reference."),
-        );
-
-        let reference1: Reference = Reference {
-            key: String::from("smith2021"),
-            fields: fields1,
-        };
-
-        let reference2: Reference = Reference {
-            key: String::from("doe2022"),
-            fields: fields2,
-        };
-
-        return [reference1, reference2];
+    use crate::{
+        fields::{Field, Fields},
+        parse::parse_bibtex,
+    };
+
+    fn expected_person(first: &str, von: &str, last: &str, jr: &str) -> Author {
+        Author {
+            name: AuthorName::Person {
+                first: String::from(first),
+                von: String::from(von),
+                last: String::from(last),
+                jr: String::from(jr),
+            },
+        }
     }
 
     #[test]
@@ -260,98 +576,235 @@ mod tests {
         {
             let test_author = String::from("Smith, John");
             let extracted_authors = extract_authors_from_string(&test_author);
-            let expected: Vec<Author> = vec![Author {
-                name: AuthorName::FirstNameLastName {
-                    first_name: String::from("John"),
-                    last_name: String::from("Smith"),
-                },
-            }];
+            let expected: Vec<Author> = vec![expected_person("John", "", "Smith", "")];
 
             assert_eq!(expected, extracted_authors);
         }
         {
             let test_author = String::from("John Smith");
             let extracted_authors = extract_authors_from_string(&test_author);
-            let expected: Vec<Author> = vec![Author {
-                name: AuthorName::FirstNameLastName {
-                    first_name: String::from("John"),
-                    last_name: String::from("Smith"),
-                },
-            }];
+            let expected: Vec<Author> = vec![expected_person("John", "", "Smith", "")];
 
             assert_eq!(expected, extracted_authors);
         }
         {
             let test_author = String::from("Juan Pablo Fernández de Calderón García-Iglesias");
             let extracted_authors = extract_authors_from_string(&test_author);
-            let expected: Vec<Author> = vec![Author {
-                name: AuthorName::FullName(test_author),
-            }];
+            let expected: Vec<Author> = vec![expected_person(
+                "Juan Pablo Fernández",
+                "de",
+                "Calderón García-Iglesias",
+                "",
+            )];
 
             assert_eq!(expected, extracted_authors);
         }
         {
             let test_author = String::from("Marieke M.A. Hendriksen");
             let extracted_authors = extract_authors_from_string(&test_author);
-            let expected: Vec<Author> = vec![Author {
-                name: AuthorName::FirstNameLastName {
-                    first_name: String::from("Marieke M.A."),
-                    last_name: String::from("Hendriksen"),
-                },
-            }];
+            let expected: Vec<Author> = vec![expected_person("Marieke M.A.", "", "Hendriksen", "")];
 
             assert_eq!(expected, extracted_authors);
         }
         {
             let test_author = String::from("Hendriksen, Marieke M.A.");
             let extracted_authors = extract_authors_from_string(&test_author);
-            let expected: Vec<Author> = vec![Author {
-                name: AuthorName::FirstNameLastName {
-                    first_name: String::from("Marieke M.A."),
-                    last_name: String::from("Hendriksen"),
-                },
-            }];
+            let expected: Vec<Author> = vec![expected_person("Marieke M.A.", "", "Hendriksen", "")];
 
             assert_eq!(expected, extracted_authors);
         }
-    }
+        {
+            let test_author = String::from("van der Berg, Jan");
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![expected_person("Jan", "van der", "Berg", "")];
 
-    #[test]
-    fn test_is_initials() {
+            assert_eq!(expected, extracted_authors);
+        }
         {
-            let test_string = String::from("");
-            let result = is_initials(&test_string);
-            assert_eq!(false, result);
+            let test_author = String::from("Ludwig van Beethoven");
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![expected_person("Ludwig", "van", "Beethoven", "")];
+
+            assert_eq!(expected, extracted_authors);
         }
         {
-            let test_string = String::from("A.B.");
-            let result = is_initials(&test_string);
-            assert_eq!(true, result);
+            let test_author = String::from("King, Jr., Martin Luther");
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![expected_person("Martin Luther", "", "King", "Jr.")];
+
+            assert_eq!(expected, extracted_authors);
         }
         {
-            let test_string = String::from("AB");
-            let result = is_initials(&test_string);
-            assert_eq!(true, result);
+            // Capitalized particles are part of the last name when the name is written with a comma
+            let test_author = String::from("Van Den Bos, Maarten");
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![expected_person("Maarten", "", "Van Den Bos", "")];
+
+            assert_eq!(expected, extracted_authors);
         }
         {
-            let test_string = String::from("DABS");
-            let result = is_initials(&test_string);
-            assert_eq!(true, result);
+            let test_author = String::from("{World Health Organization}");
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![Author {
+                name: AuthorName::FullName(String::from("World Health Organization")),
+            }];
+
+            assert_eq!(expected, extracted_authors);
         }
         {
-            let test_string = String::from("d.a.b.s.");
-            let result = is_initials(&test_string);
-            assert_eq!(true, result);
+            // "and" inside braces does not separate names, and "AND" does
+            let test_author = String::from("{Barnes and Noble} AND Smith, John and others");
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![
+                Author {
+                    name: AuthorName::FullName(String::from("Barnes and Noble")),
+                },
+                expected_person("John", "", "Smith", ""),
+                Author {
+                    name: AuthorName::Others,
+                },
+            ];
+
+            assert_eq!(expected, extracted_authors);
         }
         {
-            let test_string = String::from("Pablo");
-            let result = is_initials(&test_string);
-            assert_eq!(false, result);
+            let test_author = String::from(
+                "G{\\\"u}{\\c{c}}l{\\\"u}t{\\\"u}rk, Ya{\\u{g}}mur and\n   {\\'E}mile~Zola and Liem, {Cynthia C.S.}",
+            );
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![
+                expected_person(
+                    "Ya{\\u{g}}mur",
+                    "",
+                    "G{\\\"u}{\\c{c}}l{\\\"u}t{\\\"u}rk",
+                    "",
+                ),
+                expected_person("{\\'E}mile", "", "Zola", ""),
+                expected_person("{Cynthia C.S.}", "", "Liem", ""),
+            ];
+
+            assert_eq!(expected, extracted_authors);
         }
         {
-            let test_string = String::from("martin");
-            let result = is_initials(&test_string);
-            assert_eq!(false, result);
+            // Lowercase special characters make a word part of the von part
+            let test_author = String::from("Jean {\\'e}l Martin and Charles {\\\"O}ber Smith");
+            let extracted_authors = extract_authors_from_string(&test_author);
+            let expected: Vec<Author> = vec![
+                expected_person("Jean", "{\\'e}l", "Martin", ""),
+                expected_person("Charles {\\\"O}ber", "", "Smith", ""),
+            ];
+
+            assert_eq!(expected, extracted_authors);
+        }
+    }
+
+    #[test]
+    fn test_format_authors() {
+        let format = |authors: &str| format_authors(&extract_authors_from_string(authors));
+
+        assert_eq!(
+            Some(String::from("van der Berg, J; King, Jr., M; et al.")),
+            format("Jan van der Berg and King, Jr., Martin Luther and others")
+        );
+        assert_eq!(
+            Some(String::from("World Health Organization")),
+            format("{World Health Organization}")
+        );
+        assert_eq!(None, format(""));
+        // LaTeX in names is decoded
+        assert_eq!(
+            Some(String::from("Gödel, K; Zola, É")),
+            format("G{\\\"o}del, Kurt and {\\'E}mile Zola")
+        );
+    }
+
+    #[test]
+    fn test_ord_is_consistent_with_eq() {
+        let bibtex = String::from(
+            "@article{a, author = {Doe, Jane}, year = 2020, title = {Same}, journal = {One}}
+             @article{a, author = {Doe, Jane}, year = 2020, title = {Same}, journal = {Two}}
+             @article{a, author = {Doe, Jane}, year = 2020, title = {Same}, journal = {One}}
+             @article{b, author = {van Beethoven, Ludwig}, year = 1800}",
+        );
+        let references = parse_bibtex(bibtex, "test.bib").references;
+
+        assert_ne!(references[0], references[1]);
+        assert_ne!(std::cmp::Ordering::Equal, references[0].cmp(&references[1]));
+        assert_eq!(references[0], references[2]);
+        assert_eq!(std::cmp::Ordering::Equal, references[0].cmp(&references[2]));
+        // Particles are not sorted by
+        assert!(references[3] < references[0]);
+
+        let set: std::collections::BTreeSet<&Reference> = references.iter().collect();
+        assert_eq!(3, set.len());
+    }
+
+    #[test]
+    fn test_is_duplicate_of() {
+        let bibtex = String::from(
+            "@article{a, title = {Affective {P}olarization}, year = 2020, doi = {10.1000/ABC}}
+             @article{b, title = {Affective polarization}, year = 2020}
+             @article{c, title = {Affective Polarization}, year = 2021}
+             @article{d, title = {Something else}, doi = {https://doi.org/10.1000/abc}}
+             @article{e, title = {Affective Polarization}, year = 2020, doi = {10.1000/other}}",
+        );
+        let references = parse_bibtex(bibtex, "test.bib").references;
+        let is_duplicate = |a: usize, b: usize| references[a].is_duplicate_of(&references[b]);
+
+        assert!(is_duplicate(0, 1));
+        assert!(!is_duplicate(0, 2));
+        assert!(is_duplicate(0, 3));
+        // Different DOIs are different publications, even with the same title
+        assert!(!is_duplicate(0, 4));
+        assert!(is_duplicate(1, 4));
+    }
+
+    #[test]
+    fn test_to_bibtex_encodes_unicode_values() {
+        let mut fields = Fields::new();
+        for (name, value) in [
+            ("title", "Café & 100%"),
+            ("URL", "https://x.org/a_b%20c"),
+            ("doi", "10.1000/a_b"),
+        ] {
+            fields.insert(Field {
+                name: name.to_string(),
+                value: value.to_string(),
+                raw_value: None,
+            });
         }
+        let reference = Reference::new(String::from("misc"), String::from("a"), fields);
+        assert_eq!(
+            "@misc{a,\n    title = {Caf{\\'e} \\& 100\\%},\n    URL = {https://x.org/a_b%20c},\n    doi = {10.1000/a_b},\n}\n",
+            reference.to_bibtex()
+        );
+    }
+
+    #[test]
+    fn test_set_keyword() {
+        let bibtex = "@misc{a, Keywords = {voting; elections}}\n@misc{b, title = {B}}";
+        let mut references = parse_bibtex(bibtex.to_string(), "test.bib").references;
+
+        // The separator of the file is kept, and keywords are compared without regard to case
+        let reference = &mut references[0];
+        assert!(reference.set_keyword("Polling", true));
+        assert!(!reference.set_keyword("VOTING", true));
+        assert_eq!(vec!["voting", "elections", "Polling"], reference.keywords());
+        assert!(reference
+            .to_bibtex()
+            .contains("Keywords = {voting; elections; Polling},"));
+        assert!(reference.set_keyword("elections", false));
+        assert!(!reference.set_keyword("elections", false));
+        assert_eq!(vec!["voting", "Polling"], reference.keywords());
+
+        // The field is added for the first keyword and removed with the last one
+        let reference = &mut references[1];
+        assert!(reference.set_keyword("polls", true));
+        assert_eq!(
+            Some(&String::from("polls")),
+            reference.fields.get("keywords")
+        );
+        assert!(reference.set_keyword("polls", false));
+        assert_eq!(None, reference.fields.get("keywords"));
     }
 }
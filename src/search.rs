@@ -0,0 +1,1518 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt,
+};
+
+use crate::{
+    normalize::{normalize, normalize_latex, NormalizedText},
+    reference::Reference,
+};
+
+/// A parsed search query.
+///
+/// Queries are made of terms, which are combined with `AND` (the default when terms are simply
+/// written next to each other), `OR` and `NOT` (or a leading `-`), and can be grouped with parentheses.
+/// Plain words match fuzzily, while phrases in quotes and terms for a specific field match exactly:
+///
+/// ```text
+/// author:smith year:2015..2020 type:article -keywords:review "exact phrase"
+/// (title:bayesian OR title:"markov chain") NOT author:doe
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// Matches references in which a word of the key, authors, title, year or keywords fuzzily matches the
+    /// word, or in which any field contains it. The better the fuzzy match, the higher the reference is ranked.
+    Fuzzy(String),
+    /// Matches references in which the text occurs in the given field, ignoring case. Without a field,
+    /// the key, the entry type and every field are searched.
+    Contains {
+        field: Option<String>,
+        text: String,
+    },
+    /// Matches references where the given field is a number within the range. Either end may be left open.
+    Range {
+        field: String,
+        from: Option<u32>,
+        to: Option<u32>,
+    },
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+/// A malformed search query, pointing to where the problem was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    /// 1-based column number, counted in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (column {})", self.message, self.column)
+    }
+}
+
+impl Error for QueryError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Term {
+        field: Option<String>,
+        text: String,
+        quoted: bool,
+    },
+}
+
+/// Parses a search query. See [`Query`] for the syntax. The texts to search for are normalized in the
+/// same way as the references in the index, stemming them if `stem` is set.
+pub fn parse_query(query: &str, stem: bool) -> Result<Query, QueryError> {
+    let mut parser = QueryParser {
+        tokens: tokenize(query)?,
+        stem,
+        position: 0,
+        end_column: query.chars().count() + 1,
+    };
+
+    let parsed = parser.parse_or()?;
+    match parser.tokens.get(parser.position) {
+        None => Ok(parsed),
+        Some((_, column)) => Err(QueryError {
+            column: *column,
+            message: String::from("unexpected ')'"),
+        }),
+    }
+}
+
+/// Splits a query into tokens, each with the column at which it starts.
+fn tokenize(query: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let column = i + 1;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((Token::LeftParen, column));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((Token::RightParen, column));
+                i += 1;
+            }
+            '-' => {
+                tokens.push((Token::Not, column));
+                i += 1;
+            }
+            '"' => {
+                let text;
+                (text, i) = read_phrase(&chars, i)?;
+                tokens.push((
+                    Token::Term {
+                        field: None,
+                        text,
+                        quoted: true,
+                    },
+                    column,
+                ));
+            }
+            _ => {
+                let start = i;
+                // Quotes inside a word are part of it (`G{\"o}del`), unless they start the value of a field
+                while i < chars.len()
+                    && !is_word_boundary(chars[i])
+                    && !(chars[i] == '"' && chars[i - 1] == ':')
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+
+                let token = match word.split_once(':') {
+                    Some((field, value)) if is_field_name(field) => {
+                        let field = Some(field.to_lowercase());
+                        if !value.is_empty() {
+                            Token::Term {
+                                field,
+                                text: value.to_string(),
+                                quoted: false,
+                            }
+                        } else if chars.get(i) == Some(&'"') {
+                            let text;
+                            (text, i) = read_phrase(&chars, i)?;
+                            Token::Term {
+                                field,
+                                text,
+                                quoted: true,
+                            }
+                        } else {
+                            return Err(QueryError {
+                                column,
+                                message: format!("expected a value after '{}'", word),
+                            });
+                        }
+                    }
+                    _ => match word.as_str() {
+                        "AND" => Token::And,
+                        "OR" => Token::Or,
+                        "NOT" => Token::Not,
+                        _ => Token::Term {
+                            field: None,
+                            text: word,
+                            quoted: false,
+                        },
+                    },
+                };
+                tokens.push((token, column));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads a phrase in double quotes starting at `start`, returning it and the position after the closing quote.
+fn read_phrase(chars: &[char], start: usize) -> Result<(String, usize), QueryError> {
+    let length = chars[start + 1..]
+        .iter()
+        .position(|&c| c == '"')
+        .ok_or(QueryError {
+            column: start + 1,
+            message: String::from("unterminated quote"),
+        })?;
+    let phrase = chars[start + 1..start + 1 + length].iter().collect();
+    Ok((phrase, start + length + 2))
+}
+
+fn is_word_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')')
+}
+
+fn is_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// A recursive descent parser over the tokens of a query. `OR` binds more loosely than `AND`,
+/// which binds more loosely than `NOT`.
+struct QueryParser {
+    tokens: Vec<(Token, usize)>,
+    stem: bool,
+    position: usize,
+    /// The column just after the end of the query, for errors about a query that ends too early
+    end_column: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn parse_or(&mut self) -> Result<Query, QueryError> {
+        let mut alternatives = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            alternatives.push(self.parse_and()?);
+        }
+
+        Ok(match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => Query::Or(alternatives),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Query, QueryError> {
+        let mut parts = vec![self.parse_not()?];
+        loop {
+            match self.peek() {
+                None | Some(Token::Or) | Some(Token::RightParen) => break,
+                Some(Token::And) => {
+                    self.position += 1;
+                    parts.push(self.parse_not()?);
+                }
+                // Terms written next to each other must all match
+                Some(_) => parts.push(self.parse_not()?),
+            }
+        }
+
+        Ok(match parts.len() {
+            1 => parts.remove(0),
+            _ => Query::And(parts),
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<Query, QueryError> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, QueryError> {
+        let Some((token, column)) = self.tokens.get(self.position).cloned() else {
+            return Err(QueryError {
+                column: self.end_column,
+                message: String::from("expected a search term at the end of the query"),
+            });
+        };
+        self.position += 1;
+
+        match token {
+            Token::LeftParen => {
+                let query = self.parse_or()?;
+                if self.peek() != Some(&Token::RightParen) {
+                    return Err(QueryError {
+                        column,
+                        message: String::from("unclosed '('"),
+                    });
+                }
+                self.position += 1;
+                Ok(query)
+            }
+            Token::RightParen => Err(QueryError {
+                column,
+                message: String::from("expected a search term before ')'"),
+            }),
+            Token::And | Token::Or | Token::Not => {
+                let keyword = match token {
+                    Token::And => "AND",
+                    Token::Or => "OR",
+                    _ => "NOT",
+                };
+                Err(QueryError {
+                    column,
+                    message: format!("expected a search term before '{}'", keyword),
+                })
+            }
+            Token::Term {
+                field,
+                text,
+                quoted,
+            } => term(field, text, quoted, column, self.stem),
+        }
+    }
+}
+
+/// Builds the query for a single term, which is a range if it is a field followed by `from..to`.
+fn term(
+    field: Option<String>,
+    text: String,
+    quoted: bool,
+    column: usize,
+    stem: bool,
+) -> Result<Query, QueryError> {
+    let range = if quoted { None } else { text.split_once("..") };
+    match (field, range) {
+        (Some(field), Some((from, to))) => {
+            let invalid_range = || QueryError {
+                column,
+                message: format!(
+                    "invalid range '{}', expected numbers such as {}:2015..2020",
+                    text, field
+                ),
+            };
+            let parse_bound = |bound: &str| match bound {
+                "" => Ok(None),
+                _ => bound.parse().map(Some).map_err(|_| invalid_range()),
+            };
+            let (from, to) = (parse_bound(from)?, parse_bound(to)?);
+            if from.is_none() && to.is_none() {
+                return Err(invalid_range());
+            }
+            Ok(Query::Range { field, from, to })
+        }
+        (None, _) if !quoted => {
+            // Fuzzy terms match single words, so all words of a term like "self-report" have to match
+            let text = normalize_latex(&text, stem).as_string();
+            let mut words: Vec<Query> = text
+                .split_whitespace()
+                .map(|word| Query::Fuzzy(word.to_string()))
+                .collect();
+            Ok(match words.len() {
+                0 => Query::Fuzzy(text),
+                1 => words.remove(0),
+                _ => Query::And(words),
+            })
+        }
+        (field, _) => Ok(Query::Contains {
+            field,
+            text: normalize_latex(&text, stem).as_string(),
+        }),
+    }
+}
+
+/// Everything that is needed to search the references quickly, built once when the file is loaded.
+///
+/// The words of the references are indexed, so that only the references with a word that can match a
+/// term have to be checked. References are indexed by an id that doesn't change when references are
+/// inserted or removed before them, so that the words index doesn't have to be changed then.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// The normalized texts of every reference, by its index in the list of references
+    pub references: Vec<IndexedReference>,
+    /// The id of every reference, by index
+    ids: Vec<usize>,
+    /// The index of the reference with every id, or `None` if it was removed
+    indices: Vec<Option<usize>>,
+    /// Every word that occurs in the references. Words that no longer occur are kept, since they are few.
+    words: Vec<IndexedWord>,
+    /// The position of every word in `words`
+    word_positions: HashMap<String, usize>,
+    /// For every three consecutive characters, the positions of the words that contain them, so that the
+    /// words that contain a text can be found without checking all of them
+    trigrams: HashMap<[char; 3], Vec<usize>>,
+    /// The ids of the references with a field that starts with a number, by the field and the number
+    numbers: BTreeMap<(String, u32), Vec<usize>>,
+}
+
+#[derive(Debug)]
+struct IndexedWord {
+    chars: Vec<char>,
+    mask: u64,
+    /// The ids of the references it occurs in, in order
+    ids: Vec<usize>,
+    /// The ids of the references in which it occurs in a text that fuzzy terms are matched against, in order
+    ranked_ids: Vec<usize>,
+}
+
+impl SearchIndex {
+    pub fn new(references: &[Reference], stem: bool) -> Self {
+        let mut index = SearchIndex::default();
+        for (id, reference) in references.iter().enumerate() {
+            index.ids.push(id);
+            index.indices.push(Some(id));
+            index
+                .references
+                .push(IndexedReference::new(reference, stem));
+            index.index_words(id);
+        }
+        index
+    }
+
+    /// Indexes a reference that was inserted at the given index, so that the indices of the references from
+    /// there on go up by one
+    pub fn insert(&mut self, index: usize, reference: &Reference, stem: bool) {
+        let id = self.indices.len();
+        self.ids.insert(index, id);
+        self.indices.push(Some(index));
+        self.references
+            .insert(index, IndexedReference::new(reference, stem));
+        self.update_indices(index + 1);
+        self.index_words(id);
+    }
+
+    /// Removes the reference at the given index, so that the indices of the references after it go down by one
+    pub fn remove(&mut self, index: usize) {
+        let id = self.ids[index];
+        self.unindex_words(id);
+        self.ids.remove(index);
+        self.indices[id] = None;
+        self.references.remove(index);
+        self.update_indices(index);
+    }
+
+    /// Indexes the reference at the given index again after it was changed
+    pub fn update(&mut self, index: usize, reference: &Reference, stem: bool) {
+        let id = self.ids[index];
+        self.unindex_words(id);
+        self.references[index] = IndexedReference::new(reference, stem);
+        self.index_words(id);
+    }
+
+    /// Updates the indices of the ids of the references from the given index on, after they moved
+    fn update_indices(&mut self, from: usize) {
+        for (index, &id) in self.ids.iter().enumerate().skip(from) {
+            self.indices[id] = Some(index);
+        }
+    }
+
+    fn index_words(&mut self, id: usize) {
+        let Some(reference) = self.indices[id].map(|index| &self.references[index]) else {
+            return;
+        };
+        let (words, numbers) = (reference.words(), reference.numbers());
+        for (word, ranked) in words {
+            let position = self.word_position(word);
+            let indexed_word = &mut self.words[position];
+            insert_sorted(&mut indexed_word.ids, id);
+            if ranked {
+                insert_sorted(&mut indexed_word.ranked_ids, id);
+            }
+        }
+        for number in numbers {
+            insert_sorted(self.numbers.entry(number).or_default(), id);
+        }
+    }
+
+    fn unindex_words(&mut self, id: usize) {
+        let Some(reference) = self.indices[id].map(|index| &self.references[index]) else {
+            return;
+        };
+        let remove = |ids: &mut Vec<usize>| {
+            if let Ok(position) = ids.binary_search(&id) {
+                ids.remove(position);
+            }
+        };
+        for (word, _) in reference.words() {
+            if let Some(&position) = self.word_positions.get(&word) {
+                remove(&mut self.words[position].ids);
+                remove(&mut self.words[position].ranked_ids);
+            }
+        }
+        for number in reference.numbers() {
+            if let Some(ids) = self.numbers.get_mut(&number) {
+                remove(ids);
+                if ids.is_empty() {
+                    self.numbers.remove(&number);
+                }
+            }
+        }
+    }
+
+    /// The position of a word in `words`, adding it if it is new
+    fn word_position(&mut self, word: String) -> usize {
+        if let Some(&position) = self.word_positions.get(&word) {
+            return position;
+        }
+        let position = self.words.len();
+        let chars: Vec<char> = word.chars().collect();
+        for trigram in chars.windows(3) {
+            let positions = self
+                .trigrams
+                .entry([trigram[0], trigram[1], trigram[2]])
+                .or_default();
+            // A trigram can occur more than once in a word
+            if positions.last() != Some(&position) {
+                positions.push(position);
+            }
+        }
+        self.words.push(IndexedWord {
+            mask: char_mask(&chars),
+            chars,
+            ids: Vec::new(),
+            ranked_ids: Vec::new(),
+        });
+        self.word_positions.insert(word, position);
+        position
+    }
+
+    /// The indices of the references that match the query, with their scores, best first.
+    pub fn search(&self, query: &Query) -> Vec<(usize, u32)> {
+        let mut hits: Vec<(usize, u32)> = match self.candidates(query) {
+            Some(candidates) => {
+                let mut candidates: Vec<usize> = candidates
+                    .into_iter()
+                    .filter_map(|id| self.indices[id])
+                    .collect();
+                candidates.sort_unstable();
+                candidates
+                    .into_iter()
+                    .filter_map(|index| Some((index, query.score(&self.references[index])?)))
+                    .collect()
+            }
+            None => self
+                .references
+                .iter()
+                .enumerate()
+                .filter_map(|(index, reference)| Some((index, query.score(reference)?)))
+                .collect(),
+        };
+        // Equally good matches stay in their original order
+        hits.sort_by_key(|&(_, score)| Reverse(score));
+        hits
+    }
+
+    /// The ids of the references that could match the query, found with the words index, or `None`
+    /// if the index can't narrow it down and every reference has to be checked.
+    fn candidates(&self, query: &Query) -> Option<HashSet<usize>> {
+        match query {
+            Query::Fuzzy(text) => {
+                let pattern: Vec<char> = text.chars().collect();
+                // Fuzzy terms are matched against single words, so a term that isn't one can't be looked up
+                if pattern.is_empty() || !pattern.iter().all(|c| c.is_alphanumeric()) {
+                    return None;
+                }
+                let pattern_mask = char_mask(&pattern);
+                let mut candidates = HashSet::new();
+                for word in &self.words {
+                    // Every character of the pattern that is not in the word is a typo
+                    let missing = (pattern_mask & !word.mask).count_ones() as usize;
+                    if missing > allowed_typos(pattern.len()) {
+                        continue;
+                    }
+                    if missing == 0 && contains(&word.chars, &pattern) {
+                        candidates.extend(&word.ids);
+                    } else if word_match(&pattern, &word.chars).is_some() {
+                        candidates.extend(&word.ranked_ids);
+                    }
+                }
+                Some(candidates)
+            }
+            Query::Contains { text, .. } => {
+                // Every word of the text has to occur in the reference, possibly as part of a longer word
+                let chars: Vec<char> = text.chars().collect();
+                words(&chars)
+                    .map(|word| self.containing(word))
+                    .reduce(|a, b| &a & &b)
+            }
+            Query::Range { field, from, to } => {
+                let (from, to) = (from.unwrap_or(0), to.unwrap_or(u32::MAX));
+                if from > to {
+                    return Some(HashSet::new());
+                }
+                Some(
+                    self.numbers
+                        .range((field.clone(), from)..=(field.clone(), to))
+                        .flat_map(|(_, ids)| ids.iter().copied())
+                        .collect(),
+                )
+            }
+            // The references that don't match a term can't be found with the index
+            Query::Not(_) => None,
+            Query::And(queries) => queries
+                .iter()
+                .filter_map(|query| self.candidates(query))
+                .reduce(|a, b| &a & &b),
+            Query::Or(queries) => queries
+                .iter()
+                .map(|query| self.candidates(query))
+                .reduce(|a, b| Some(&a? | &b?))
+                .flatten(),
+        }
+    }
+
+    /// The ids of the references with a word that contains the text
+    fn containing(&self, text: &[char]) -> HashSet<usize> {
+        // Only the words that have every three consecutive characters of the text can contain it, so the
+        // words with the rarest of them are checked
+        let rarest = text
+            .windows(3)
+            .map(|trigram| {
+                self.trigrams
+                    .get(&[trigram[0], trigram[1], trigram[2]])
+                    .map_or(&[][..], Vec::as_slice)
+            })
+            .min_by_key(|positions| positions.len());
+        let positions: Box<dyn Iterator<Item = usize>> = match rarest {
+            Some(positions) => Box::new(positions.iter().copied()),
+            None => Box::new(0..self.words.len()),
+        };
+        positions
+            .map(|position| &self.words[position])
+            .filter(|word| contains(&word.chars, text))
+            .flat_map(|word| word.ids.iter().copied())
+            .collect()
+    }
+}
+
+/// Adds an id to a list of ids in order, unless it is already in it
+fn insert_sorted(ids: &mut Vec<usize>, id: usize) {
+    // New references have the highest id, so that they can simply be added at the end
+    if ids.last().is_none_or(|&last| last < id) {
+        ids.push(id);
+    } else if let Err(position) = ids.binary_search(&id) {
+        ids.insert(position, id);
+    }
+}
+
+/// The texts of a reference that are searched, normalized once when the file is loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedReference {
+    /// The key, entry type, authors, year and title as they are shown in the table
+    pub columns: [String; 5],
+    pub key: NormalizedText,
+    pub entry_type: String,
+    /// What fuzzy terms are matched against: the columns except for the entry type, and the keywords,
+    /// with the characters that occur in each of them to quickly skip texts that can't match
+    pub ranked: Vec<(NormalizedText, u64)>,
+    /// Every field, by lowercase name
+    pub fields: Vec<(String, NormalizedText)>,
+}
+
+impl IndexedReference {
+    pub fn new(reference: &Reference, stem: bool) -> Self {
+        let columns = reference.as_array().map(Option::unwrap_or_default);
+        let [key, _entry_type, author, year, title] = &columns;
+        let keywords = reference.decoded_field("keywords").unwrap_or_default();
+        let ranked = [key, author, year, title, &keywords]
+            .into_iter()
+            .filter(|text| !text.is_empty())
+            .map(|text| {
+                let normalized = normalize(text, stem);
+                let mask = char_mask(&normalized.chars);
+                (normalized, mask)
+            })
+            .collect();
+
+        IndexedReference {
+            key: normalize(reference.key(), stem),
+            entry_type: reference.entry_type(),
+            columns,
+            ranked,
+            fields: reference
+                .fields
+                .iter()
+                .map(|field| {
+                    (
+                        field.name.to_lowercase(),
+                        normalize_latex(&field.value, stem),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The words that are put in the words index: those of the key, the fields and the texts that fuzzy terms
+    /// are matched against, and the entry type, with whether they occur in the latter texts
+    fn words(&self) -> Vec<(String, bool)> {
+        let entry_type: Vec<char> = self.entry_type.chars().collect();
+        let texts = [&self.key]
+            .into_iter()
+            .chain(self.fields.iter().map(|(_, value)| value));
+        let mut words: Vec<(&[char], bool)> = texts
+            .flat_map(|text| words(&text.chars))
+            .chain([&entry_type[..]])
+            .map(|word| (word, false))
+            .chain(
+                self.ranked
+                    .iter()
+                    .flat_map(|(text, _)| words(&text.chars))
+                    .map(|word| (word, true)),
+            )
+            .collect();
+        // Sorting puts the occurrences of a word in the ranked texts after its other occurrences
+        words.sort_unstable();
+        words.dedup_by(|word, previous| {
+            let same = word.0 == previous.0;
+            if same {
+                previous.1 |= word.1;
+            }
+            same
+        });
+        words
+            .into_iter()
+            .map(|(word, ranked)| (word.iter().collect(), ranked))
+            .collect()
+    }
+
+    /// The fields that start with a number, with the number, for finding the references in a range
+    fn numbers(&self) -> Vec<(String, u32)> {
+        self.fields
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), leading_number(value)?)))
+            .collect()
+    }
+
+    fn field(&self, name: &str) -> Option<&NormalizedText> {
+        self.fields
+            .iter()
+            .find(|(field_name, _)| field_name == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Whether the key, the entry type or any field contains the (normalized) text.
+    fn contains_anywhere(&self, text: &[char]) -> bool {
+        contains(&self.key.chars, text)
+            || self.entry_type.contains(&text.iter().collect::<String>())
+            || self
+                .fields
+                .iter()
+                .any(|(_, value)| contains(&value.chars, text))
+    }
+}
+
+/// The words (runs of letters and digits) of a normalized text.
+fn words(text: &[char]) -> impl Iterator<Item = &[char]> {
+    word_ranges(text).map(|(start, end)| &text[start..end])
+}
+
+/// Where the words of a normalized text start and end.
+fn word_ranges(text: &[char]) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        start += text[start..].iter().position(|c| c.is_alphanumeric())?;
+        let end = start
+            + text[start..]
+                .iter()
+                .position(|c| !c.is_alphanumeric())
+                .unwrap_or(text.len() - start);
+        let range = (start, end);
+        start = end;
+        Some(range)
+    })
+}
+
+/// A set of the characters in a text, as bits: one for each letter and digit, and a few shared by the
+/// other characters.
+fn char_mask(text: &[char]) -> u64 {
+    text.iter().fold(0, |mask, &c| {
+        let bit = match c {
+            'a'..='z' => c as u32 - 'a' as u32,
+            '0'..='9' => 26 + c as u32 - '0' as u32,
+            _ => 36 + c as u32 % 28,
+        };
+        mask | 1 << bit
+    })
+}
+
+impl Query {
+    pub fn matches(&self, reference: &IndexedReference) -> bool {
+        self.score(reference).is_some()
+    }
+
+    /// How well a reference matches the query, or `None` if it doesn't match. Only fuzzy matches add
+    /// to the score; the other terms just have to match.
+    pub fn score(&self, reference: &IndexedReference) -> Option<u32> {
+        let matched = |is_match: bool| is_match.then_some(0);
+        match self {
+            Query::Fuzzy(text) => {
+                let pattern: Vec<char> = text.chars().collect();
+                let pattern_mask = char_mask(&pattern);
+                reference
+                    .ranked
+                    .iter()
+                    // Every character of the pattern that is not in the text is a typo
+                    .filter(|(_, mask)| {
+                        (pattern_mask & !mask).count_ones() as usize <= allowed_typos(pattern.len())
+                    })
+                    .filter_map(|(ranked_text, _)| fuzzy_match(&pattern, &ranked_text.chars))
+                    .map(|fuzzy_match| fuzzy_match.score)
+                    .max()
+                    .or_else(|| matched(reference.contains_anywhere(&pattern)))
+            }
+            Query::Contains { field, text } => {
+                let pattern: Vec<char> = text.chars().collect();
+                matched(match field.as_deref() {
+                    None => reference.contains_anywhere(&pattern),
+                    Some("key") => contains(&reference.key.chars, &pattern),
+                    Some("type") => reference.entry_type == *text,
+                    Some(field) => reference
+                        .field(field)
+                        .is_some_and(|value| contains(&value.chars, &pattern)),
+                })
+            }
+            Query::Range { field, from, to } => matched(
+                reference
+                    .field(field)
+                    .and_then(leading_number)
+                    .is_some_and(|number| {
+                        from.is_none_or(|from| number >= from) && to.is_none_or(|to| number <= to)
+                    }),
+            ),
+            Query::Not(query) => matched(!query.matches(reference)),
+            Query::And(queries) => queries.iter().map(|query| query.score(reference)).sum(),
+            Query::Or(queries) => queries
+                .iter()
+                .filter_map(|query| query.score(reference))
+                .max(),
+        }
+    }
+
+    /// The indices of the characters of a (displayed) text that match the query, to highlight them.
+    /// Terms that must not match are left out.
+    pub fn highlight(&self, text: &str, stem: bool) -> Vec<usize> {
+        let text = normalize(text, stem);
+        let mut positions = Vec::new();
+        self.collect_highlights(&text.chars, &mut positions);
+
+        let mut positions: Vec<usize> = positions.into_iter().map(|i| text.origins[i]).collect();
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+
+    fn collect_highlights(&self, text: &[char], positions: &mut Vec<usize>) {
+        match self {
+            Query::Fuzzy(pattern) => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                if let Some(fuzzy_match) = fuzzy_match(&pattern, text) {
+                    positions.extend(fuzzy_match.positions);
+                }
+            }
+            Query::Contains { text: pattern, .. } => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                if pattern.is_empty() || pattern.len() > text.len() {
+                    return;
+                }
+                for start in 0..=text.len() - pattern.len() {
+                    if text[start..start + pattern.len()] == pattern[..] {
+                        positions.extend(start..start + pattern.len());
+                    }
+                }
+            }
+            Query::Range { .. } | Query::Not(_) => {}
+            Query::And(queries) | Query::Or(queries) => {
+                for query in queries {
+                    query.collect_highlights(text, positions);
+                }
+            }
+        }
+    }
+}
+
+fn contains(text: &[char], pattern: &[char]) -> bool {
+    pattern.is_empty() || text.windows(pattern.len()).any(|window| window == pattern)
+}
+
+/// A fuzzy match of a pattern in a text.
+#[derive(Debug, PartialEq)]
+struct FuzzyMatch {
+    /// Higher is better
+    score: u32,
+    /// The indices of the characters of the text that matched
+    positions: Vec<usize>,
+}
+
+const MATCH_SCORE: i32 = 16;
+const CONSECUTIVE_BONUS: i32 = 16;
+const WORD_START_BONUS: i32 = 8;
+const GAP_PENALTY: i32 = 3;
+
+/// Matches a (normalized) pattern against the words of a (normalized) text, and returns the best match.
+///
+/// The characters of the pattern have to appear in a word in the same order, and matches in which they are
+/// close together and at the start of the word score higher. Matches that are too scattered are rejected.
+/// If there is no such match, a word that is within one or two typos of the pattern (depending on its
+/// length) matches as well, with a lower score. Since a pattern only matches single words, the words
+/// index can find the references that it matches.
+fn fuzzy_match(pattern: &[char], text: &[char]) -> Option<FuzzyMatch> {
+    let mut best: Option<FuzzyMatch> = None;
+    for (start, end) in word_ranges(text) {
+        let Some(mut word_match) = word_match(pattern, &text[start..end]) else {
+            continue;
+        };
+        if best
+            .as_ref()
+            .is_none_or(|best| word_match.score > best.score)
+        {
+            word_match
+                .positions
+                .iter_mut()
+                .for_each(|position| *position += start);
+            best = Some(word_match);
+        }
+    }
+    best
+}
+
+/// Matches a (normalized) pattern against a single word.
+fn word_match(pattern: &[char], word: &[char]) -> Option<FuzzyMatch> {
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let length = pattern.len() as i32;
+    let perfect_score = length * MATCH_SCORE + (length - 1) * CONSECUTIVE_BONUS + WORD_START_BONUS;
+    subsequence_match(pattern, word)
+        .filter(|subsequence_match| subsequence_match.score as i32 * 3 >= perfect_score * 2)
+        .or_else(|| typo_match(pattern, word))
+}
+
+/// Finds the best way to match the pattern as a subsequence of the text.
+fn subsequence_match(pattern: &[char], text: &[char]) -> Option<FuzzyMatch> {
+    let (m, n) = (pattern.len(), text.len());
+    // Most texts don't contain the pattern at all, which is much quicker to check than finding the best match
+    let mut remaining = text.iter();
+    if !pattern.iter().all(|c| remaining.any(|t| t == c)) {
+        return None;
+    }
+
+    // best[i][j] is the best score for matching pattern[..=i] with pattern[i] at text[j],
+    // and previous[i][j] is where pattern[i - 1] was matched in that case
+    let mut best: Vec<Vec<Option<i32>>> = vec![vec![None; n]; m];
+    let mut previous = vec![vec![0; n]; m];
+    for i in 0..m {
+        // The best match of pattern[..i] before j, as (score + position * GAP_PENALTY, position),
+        // so that the penalty for the gap up to j can be subtracted afterwards
+        let mut best_before: Option<(i32, usize)> = None;
+        for j in 0..n {
+            if i > 0 && j > 0 {
+                if let Some(score) = best[i - 1][j - 1] {
+                    let candidate = score + (j - 1) as i32 * GAP_PENALTY;
+                    if best_before.is_none_or(|(best_score, _)| candidate > best_score) {
+                        best_before = Some((candidate, j - 1));
+                    }
+                }
+            }
+            if pattern[i] != text[j] {
+                continue;
+            }
+
+            let bonus = MATCH_SCORE
+                + if is_word_start(text, j) {
+                    WORD_START_BONUS
+                } else {
+                    0
+                };
+            if i == 0 {
+                best[i][j] = Some(bonus);
+                continue;
+            }
+            let consecutive = match j {
+                0 => None,
+                _ => best[i - 1][j - 1].map(|score| (score + CONSECUTIVE_BONUS, j - 1)),
+            };
+            let after_gap = best_before
+                .map(|(score, position)| (score - (j - 1) as i32 * GAP_PENALTY, position));
+            if let Some((score, position)) = consecutive.into_iter().chain(after_gap).max() {
+                best[i][j] = Some(score + bonus);
+                previous[i][j] = position;
+            }
+        }
+    }
+
+    let (score, mut j) = (0..n)
+        .filter_map(|j| best[m - 1][j].map(|score| (score, j)))
+        .max_by(|(score, j), (other_score, other_j)| score.cmp(other_score).then(other_j.cmp(j)))?;
+    let mut positions = vec![0; m];
+    for i in (0..m).rev() {
+        positions[i] = j;
+        j = previous[i][j];
+    }
+
+    Some(FuzzyMatch {
+        score: score.max(0) as u32,
+        positions,
+    })
+}
+
+/// Matches a word, or the start of a longer word that is still being typed, that is within one typo of the
+/// pattern, or two typos if the pattern is long.
+fn typo_match(pattern: &[char], word: &[char]) -> Option<FuzzyMatch> {
+    let allowed_typos = allowed_typos(pattern.len());
+    if allowed_typos == 0 {
+        return None;
+    }
+
+    let prefix_end = pattern.len().min(word.len());
+    let (typos, end) = [word.len(), prefix_end]
+        .into_iter()
+        // Every character that one word has more than the other is a typo
+        .filter(|end| end.abs_diff(pattern.len()) <= allowed_typos)
+        .map(|end| (typo_distance(pattern, &word[..end]), end))
+        .filter(|&(typos, _)| typos <= allowed_typos)
+        .min_by_key(|&(typos, _)| typos)?;
+    Some(FuzzyMatch {
+        score: ((pattern.len() - typos) as i32 * MATCH_SCORE) as u32,
+        positions: (0..end).collect(),
+    })
+}
+
+/// How many typos a search term of the given length may contain.
+fn allowed_typos(length: usize) -> usize {
+    match length {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// The number of insertions, deletions, substitutions and transpositions of adjacent characters
+/// needed to turn one word into the other (the optimal string alignment distance).
+fn typo_distance(a: &[char], b: &[char]) -> usize {
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            distances[i][j] = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distances[i][j] = distances[i][j].min(distances[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+fn is_word_start(text: &[char], position: usize) -> bool {
+    position == 0 || !text[position - 1].is_alphanumeric()
+}
+
+/// The number at the start of a value, so that `2015a` or `2015--2016` count as 2015.
+pub fn leading_number(value: &NormalizedText) -> Option<u32> {
+    let digits: String = value
+        .chars
+        .iter()
+        .skip_while(|c| c.is_whitespace())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse::parse_bibtex;
+
+    fn contains(field: Option<&str>, text: &str) -> Query {
+        Query::Contains {
+            field: field.map(String::from),
+            text: text.to_string(),
+        }
+    }
+
+    fn fuzzy(text: &str) -> Query {
+        Query::Fuzzy(text.to_string())
+    }
+
+    fn matching_keys<'a>(references: &'a [Reference], query: &str, stem: bool) -> Vec<&'a str> {
+        let query = parse_query(query, stem).unwrap();
+        references
+            .iter()
+            .filter(|reference| query.matches(&IndexedReference::new(reference, stem)))
+            .map(Reference::key)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            Ok(Query::And(vec![
+                contains(Some("author"), "smith"),
+                Query::Range {
+                    field: String::from("year"),
+                    from: Some(2015),
+                    to: Some(2020)
+                },
+                contains(Some("type"), "article"),
+                Query::Not(Box::new(contains(Some("keywords"), "review"))),
+                contains(None, "exact phrase"),
+            ])),
+            parse_query(
+                r#"Author:Smith year:2015..2020 type:article -keywords:review "Exact phrase""#,
+                false
+            )
+        );
+
+        // OR binds more loosely than AND, and parentheses group
+        assert_eq!(
+            Ok(Query::Or(vec![
+                Query::And(vec![fuzzy("a"), fuzzy("b")]),
+                fuzzy("c"),
+            ])),
+            parse_query("a AND b OR c", false)
+        );
+        assert_eq!(
+            Ok(Query::And(vec![
+                fuzzy("a"),
+                Query::Or(vec![contains(Some("title"), "deep learning"), fuzzy("c")]),
+            ])),
+            parse_query(r#"a (title:"deep learning" OR c)"#, false)
+        );
+        assert_eq!(
+            Ok(Query::Range {
+                field: String::from("year"),
+                from: None,
+                to: Some(1999)
+            }),
+            parse_query("year:..1999", false)
+        );
+        // Fuzzy terms match single words, so a term with more words is split
+        assert_eq!(
+            Ok(Query::And(vec![fuzzy("self"), fuzzy("report")])),
+            parse_query("self-report", false)
+        );
+        // Lowercase keywords are search terms
+        assert_eq!(
+            Ok(Query::And(vec![fuzzy("cats"), fuzzy("and"), fuzzy("dogs")])),
+            parse_query("cats and dogs", false)
+        );
+    }
+
+    #[test]
+    fn test_parse_query_errors() {
+        let error = |query: &str| parse_query(query, false).unwrap_err().to_string();
+
+        assert_eq!("unclosed '(' (column 3)", error("a (b OR c"));
+        assert_eq!("unexpected ')' (column 2)", error("a) b"));
+        assert_eq!("unterminated quote (column 7)", error(r#"title:"deep"#));
+        assert_eq!(
+            "expected a value after 'author:' (column 1)",
+            error("author: smith")
+        );
+        assert_eq!(
+            "expected a search term at the end of the query (column 6)",
+            error("a OR ")
+        );
+        assert_eq!(
+            "expected a search term before 'OR' (column 1)",
+            error("OR a")
+        );
+        assert_eq!(
+            "invalid range '2015..now', expected numbers such as year:2015..2020 (column 1)",
+            error("year:2015..now")
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        let chars = |text: &str| normalize(text, false).chars;
+        let positions = |pattern: &str, text: &str| {
+            fuzzy_match(&chars(pattern), &chars(text)).map(|m| m.positions)
+        };
+
+        assert_eq!(Some(vec![0, 1, 2]), positions("pol", "Polarization"));
+        // Matches at the start of words are preferred
+        assert_eq!(Some(vec![7, 8]), positions("ma", "Gamma, Markov"));
+        assert_eq!(Some(vec![0, 1, 3, 4]), positions("smth", "Smith"));
+        // Too scattered
+        assert_eq!(None, positions("smith", "some mixed items that hold"));
+        // Typos
+        assert_eq!(
+            Some((10..19).collect()),
+            positions("hartevled", "Bramson & Harteveld")
+        );
+        assert_eq!(
+            Some((0..12).collect()),
+            positions("polarisation", "polarization")
+        );
+        assert_eq!(None, positions("cat", "cut"));
+
+        // Closer matches score higher
+        let score =
+            |pattern: &str, text: &str| fuzzy_match(&chars(pattern), &chars(text)).unwrap().score;
+        assert!(score("smith", "Smith") > score("smith", "Smyth"));
+        assert!(score("bayes", "Bayesian statistics") > score("bayes", "Bays"));
+        // Patterns are matched against single words
+        assert_eq!(None, positions("bayes", "A bay of estuaries"));
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let bibtex = String::from(
+            r#"@Article{Smith2016,
+  author = {Smith, John and G{\"o}del, Kurt},
+  title = {Deep learning for cats},
+  year = {2016},
+  keywords = {survey},
+}
+@InProceedings{Smith2021,
+  author = {Smith, Jane},
+  title = {Shallow learning},
+  year = 2021,
+  keywords = {review},
+}
+@Book{Doe2015,
+  editor = {Doe, Jane},
+  title = {Deep thoughts},
+  year = {2015a},
+}"#,
+        );
+        let references = parse_bibtex(bibtex, "test.bib").references;
+        let matching_keys = |query: &str| matching_keys(&references, query, false);
+
+        assert_eq!(
+            vec!["Smith2016", "Smith2021"],
+            matching_keys("author:smith")
+        );
+        assert_eq!(vec!["Smith2016"], matching_keys("AUTHOR:gödel"));
+        assert_eq!(vec!["Smith2016"], matching_keys(r#"author:G{\"o}del"#));
+        assert_eq!(
+            vec!["Smith2016", "Doe2015"],
+            matching_keys("year:2015..2020")
+        );
+        assert_eq!(vec!["Smith2021"], matching_keys("year:2020.."));
+        assert_eq!(vec!["Smith2021"], matching_keys("type:inproceedings"));
+        assert_eq!(Vec::<&str>::new(), matching_keys("type:proceedings"));
+        assert_eq!(vec!["Doe2015"], matching_keys("key:doe"));
+        assert_eq!(
+            vec!["Smith2016", "Doe2015"],
+            matching_keys("-keywords:review")
+        );
+        assert_eq!(
+            vec!["Smith2016", "Smith2021"],
+            matching_keys(r#""learning""#)
+        );
+        assert_eq!(Vec::<&str>::new(), matching_keys(r#""learning for dogs""#));
+        assert_eq!(
+            vec!["Smith2021", "Doe2015"],
+            matching_keys("(title:shallow OR editor:doe) NOT year:2016")
+        );
+
+        // Plain words match fuzzily, in any order
+        assert_eq!(vec!["Smith2016"], matching_keys("lerning gödel"));
+        assert_eq!(vec!["Smith2016"], matching_keys("cats deep"));
+        // and also anywhere in the fields
+        assert_eq!(vec!["Smith2016"], matching_keys("survey"));
+
+        let ranked_keys = |query: &str| -> Vec<&str> {
+            let query = parse_query(query, false).unwrap();
+            let mut scored: Vec<(u32, &str)> = references
+                .iter()
+                .filter_map(|reference| {
+                    let score = query.score(&IndexedReference::new(reference, false))?;
+                    Some((score, reference.key()))
+                })
+                .collect();
+            scored.sort_by_key(|(score, _)| Reverse(*score));
+            scored.into_iter().map(|(_, key)| key).collect()
+        };
+        assert_eq!(
+            vec!["Doe2015", "Smith2016"],
+            ranked_keys("deep thoughts OR deep")
+        );
+    }
+
+    #[test]
+    fn test_highlight() {
+        let query = parse_query(r#"smth "learning" -cats"#, false).unwrap();
+        assert_eq!(vec![0, 1, 3, 4], query.highlight("Smith, J", false));
+        assert_eq!(
+            (5..13).collect::<Vec<usize>>(),
+            query.highlight("Deep learning for cats", false)
+        );
+
+        // Matches of normalized text are highlighted in the original
+        let query = parse_query("sozeri strasse", false).unwrap();
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], query.highlight("Sözeri", false));
+        assert_eq!(vec![0, 1, 2, 3, 4, 5], query.highlight("Straße", false));
+    }
+
+    #[test]
+    fn test_search_ignores_accents_and_punctuation() {
+        let bibtex = include_str!("../test_bibliography.bib").to_string();
+        let references = parse_bibtex(bibtex, "test_bibliography.bib").references;
+        let matching_keys = |query: &str| matching_keys(&references, query, false);
+
+        assert!(matching_keys("sozeri").contains(&"SözeriEtal2022"));
+        assert!(matching_keys("key:sozeri").contains(&"SözeriEtal2022"));
+        assert!(matching_keys(r#"author:S{\"o}zeri"#).contains(&"SözeriEtal2022"));
+        assert_eq!(
+            matching_keys(r#""multi party""#),
+            matching_keys(r#""multi-party""#)
+        );
+    }
+
+    #[test]
+    fn test_search_with_stemming() {
+        let bibtex = String::from(
+            "@Article{A, title = {Case studies of polarization}}\n@Article{B, title = {A study}}",
+        );
+        let references = parse_bibtex(bibtex, "test.bib").references;
+
+        assert_eq!(
+            vec!["A"],
+            matching_keys(&references, "title:studies", false)
+        );
+        assert_eq!(
+            vec!["A", "B"],
+            matching_keys(&references, "title:studies", true)
+        );
+        assert_eq!(
+            vec!["A", "B"],
+            matching_keys(&references, r#""study""#, true)
+        );
+    }
+
+    #[test]
+    fn test_search_index() {
+        let bibtex = include_str!("../test_bibliography.bib").to_string();
+        let references = parse_bibtex(bibtex, "test_bibliography.bib").references;
+        let index = SearchIndex::new(&references, false);
+
+        // Narrowing down the references with the words index finds the same matches as checking them all
+        for query in [
+            "polarization",
+            // Typos, the start of a word, part of a word and a short term
+            "polarisation hartevld",
+            "polariz",
+            "olariz",
+            "po",
+            "self-reported",
+            r#""affective polarization""#,
+            r#""olariz""#,
+            "year:2000..2010",
+            "type:book",
+            "title:nation -title:nationalism",
+            r#"(author:harteveld OR "elite groups") year:2000.."#,
+            r#"key:sozeri OR "multi-party" OR journal:studies"#,
+        ] {
+            let query = parse_query(query, false).unwrap();
+            let mut found: Vec<usize> =
+                index.search(&query).into_iter().map(|(id, _)| id).collect();
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..references.len())
+                .filter(|&id| query.matches(&index.references[id]))
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(expected, found);
+        }
+    }
+
+    #[test]
+    fn test_update_search_index() {
+        let bibtex = include_str!("../test_bibliography.bib").to_string();
+        let mut references = parse_bibtex(bibtex, "test_bibliography.bib").references;
+        let mut index = SearchIndex::new(&references, false);
+        let ids = |index: &SearchIndex, query: &str| -> Vec<usize> {
+            let query = parse_query(query, false).unwrap();
+            index.search(&query).into_iter().map(|(id, _)| id).collect()
+        };
+
+        let id = 3;
+        let old_key = format!("key:\"{}\"", references[id].key);
+        references[id].key = String::from("renamed");
+        references[id].fields.insert(crate::fields::Field {
+            name: String::from("title"),
+            value: String::from("Zyxwvut"),
+            raw_value: None,
+        });
+        index.update(id, &references[id], false);
+
+        assert_eq!(vec![id], ids(&index, "title:zyxwvut"));
+        assert_eq!(vec![id], ids(&index, "key:renamed"));
+        assert!(ids(&index, &old_key).is_empty());
+        assert_eq!("renamed", index.references[id].columns[0]);
+
+        // The references after a removed or inserted one move with it
+        let last = references.len() - 1;
+        let last_key = format!("key:\"{}\"", references[last].key);
+        index.remove(id);
+        assert!(ids(&index, "key:renamed").is_empty());
+        assert_eq!(vec![last - 1], ids(&index, &last_key));
+        index.insert(0, &references[id], false);
+        assert_eq!(vec![0], ids(&index, "key:renamed"));
+        assert_eq!(vec![last], ids(&index, &last_key));
+    }
+
+    /// Generates a bibliography with the given number of references, with made-up but realistic
+    /// authors, titles and abstracts.
+    fn generate_bibliography(count: usize) -> String {
+        const NAMES: [&str; 12] = [
+            "Smith",
+            "Garc{\\'i}a",
+            "M{\\\"u}ller",
+            "Sözeri",
+            "de Vries",
+            "Nakamura",
+            "Okafor",
+            "Kowalski",
+            "Fern{\\'a}ndez",
+            "Harteveld",
+            "Nguyen",
+            "O'Brien",
+        ];
+        const WORDS: [&str; 16] = [
+            "affective",
+            "polarization",
+            "learning",
+            "deep",
+            "networks",
+            "political",
+            "trust",
+            "migration",
+            "climate",
+            "bayesian",
+            "inference",
+            "education",
+            "media",
+            "survey",
+            "evidence",
+            "self--reported",
+        ];
+        const TYPES: [&str; 4] = ["article", "book", "inproceedings", "misc"];
+
+        // A simple linear congruential generator, so that the bibliography is the same every time
+        let mut seed: u64 = 42;
+        let mut next = |n: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+        let mut bibtex = String::new();
+        for i in 0..count {
+            let authors: Vec<String> = (0..1 + next(4))
+                .map(|_| {
+                    format!(
+                        "{}, {}.",
+                        NAMES[next(NAMES.len())],
+                        (b'A' + next(26) as u8) as char
+                    )
+                })
+                .collect();
+            let title: Vec<&str> = (0..3 + next(8)).map(|_| WORDS[next(WORDS.len())]).collect();
+            let abstract_words: Vec<&str> = (0..40).map(|_| WORDS[next(WORDS.len())]).collect();
+            bibtex.push_str(&format!(
+                "@{}{{ref{},\n  author = {{{}}},\n  title = {{{}}},\n  year = {},\n  journal = {{Journal of {}}},\n  abstract = {{{}}},\n}}\n\n",
+                TYPES[next(TYPES.len())],
+                i,
+                authors.join(" and "),
+                title.join(" "),
+                1950 + next(75),
+                WORDS[next(WORDS.len())],
+                abstract_words.join(" "),
+            ));
+        }
+        bibtex
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture bench_search` to see how long searching a
+    /// large library takes. It fails if building the index or a search takes longer than its budget, which is
+    /// ten times as long in debug builds.
+    #[test]
+    #[ignore]
+    fn bench_search() {
+        use std::time::{Duration, Instant};
+
+        let scale = if cfg!(debug_assertions) { 10 } else { 1 };
+        let (build_budget, search_budget) = (
+            Duration::from_secs(2) * scale,
+            Duration::from_millis(100) * scale,
+        );
+
+        let bibtex = generate_bibliography(20_000);
+        let start = Instant::now();
+        let references = parse_bibtex(bibtex, "generated.bib").references;
+        println!(
+            "Parsing {} references: {:?}",
+            references.len(),
+            start.elapsed()
+        );
+
+        let start = Instant::now();
+        let index = SearchIndex::new(&references, false);
+        let elapsed = start.elapsed();
+        println!("Building the index: {:?}", elapsed);
+        assert!(
+            elapsed < build_budget,
+            "building the index took {elapsed:?}"
+        );
+
+        for query in [
+            "harteveld polarization",
+            "polarisation",
+            "muller",
+            r#""affective polarization""#,
+            "author:sozeri year:2000..2010",
+            "type:book -title:learning",
+        ] {
+            let start = Instant::now();
+            let hits = index.search(&parse_query(query, false).unwrap());
+            let elapsed = start.elapsed();
+            println!("{:<32} {:>6} hits in {:?}", query, hits.len(), elapsed);
+            assert!(
+                elapsed < search_budget,
+                "searching {query} took {elapsed:?}"
+            );
+        }
+    }
+}
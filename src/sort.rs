@@ -0,0 +1,312 @@
+use std::cmp::Ordering;
+
+use crate::{
+    normalize::normalize_latex,
+    reference::{AuthorName, Reference},
+    search::leading_number,
+};
+
+/// What the table can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    Key,
+    EntryType,
+    Author,
+    Year,
+    Title,
+    Journal,
+    /// The order in the file, since new references are added at the end
+    DateAdded,
+}
+
+impl SortColumn {
+    /// All sort orders, in the order in which `s` goes through them
+    pub const ALL: [SortColumn; 7] = [
+        SortColumn::Key,
+        SortColumn::EntryType,
+        SortColumn::Author,
+        SortColumn::Year,
+        SortColumn::Title,
+        SortColumn::Journal,
+        SortColumn::DateAdded,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortColumn::Key => "key",
+            SortColumn::EntryType => "entry type",
+            SortColumn::Author => "first author",
+            SortColumn::Year => "year",
+            SortColumn::Title => "title",
+            SortColumn::Journal => "journal",
+            SortColumn::DateAdded => "date added",
+        }
+    }
+
+    /// The column of the table that shows what is sorted by, if there is one
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            SortColumn::Key => Some(0),
+            SortColumn::EntryType => Some(1),
+            SortColumn::Author => Some(2),
+            SortColumn::Year => Some(3),
+            SortColumn::Title => Some(4),
+            SortColumn::Journal | SortColumn::DateAdded => None,
+        }
+    }
+
+    pub fn from_column(column: usize) -> Option<SortColumn> {
+        SortColumn::ALL
+            .into_iter()
+            .find(|sort_column| sort_column.column() == Some(column))
+    }
+
+    pub fn next(&self) -> SortColumn {
+        let position = SortColumn::ALL.iter().position(|c| c == self).unwrap_or(0);
+        SortColumn::ALL[(position + 1) % SortColumn::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub column: SortColumn,
+    pub ascending: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self {
+            column: SortColumn::Author,
+            ascending: true,
+        }
+    }
+}
+
+/// How references are collated: by the last name of the first author (or editor), then year, title and key.
+///
+/// Names are decoded from LaTeX and compared without accents, and particles are only compared after the
+/// first name, so "Ludwig van Beethoven" is sorted under B, as "Beethoven, Ludwig van". Missing values
+/// come after present ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollationKey {
+    /// Last name, first name and particles ("von" and "Jr.") of the first author
+    author: Option<[String; 3]>,
+    year: Option<(Option<u32>, String)>,
+    title: Option<String>,
+    key: String,
+}
+
+impl CollationKey {
+    pub fn new(reference: &Reference) -> Self {
+        let normalized = |text: &str| normalize_latex(text, false).as_string().trim().to_string();
+
+        let authors = match reference.authors() {
+            authors if authors.is_empty() => reference.editors(),
+            authors => authors,
+        };
+        let author = authors.first().and_then(|author| match &author.name {
+            AuthorName::Person {
+                first,
+                von,
+                last,
+                jr,
+            } => Some([
+                normalized(last),
+                normalized(first),
+                normalized(&format!("{} {}", von, jr)),
+            ]),
+            AuthorName::FullName(name) => Some([normalized(name), String::new(), String::new()]),
+            AuthorName::Others => None,
+        });
+        let year = reference.year().map(|year| {
+            let year = normalize_latex(year, false);
+            (leading_number(&year), year.as_string().trim().to_string())
+        });
+
+        Self {
+            author,
+            year,
+            title: reference.title().map(|title| normalized(title)),
+            key: reference.key.clone(),
+        }
+    }
+
+    pub fn has_author(&self) -> bool {
+        self.author.is_some()
+    }
+}
+
+impl PartialOrd for CollationKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CollationKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        missing_last(&self.author, &other.author)
+            .then_with(|| missing_last(&self.year, &other.year))
+            .then_with(|| missing_last(&self.title, &other.title))
+            .then_with(|| self.key.cmp(&other.key))
+    }
+}
+
+fn missing_last<T: Ord>(a: &Option<T>, b: &Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    }
+}
+
+/// The values a reference is sorted by, decoded and normalized once, so that sorting doesn't have to
+/// format the fields again for every comparison.
+#[derive(Debug, Clone)]
+pub struct SortKeys {
+    key: String,
+    entry_type: String,
+    collation: CollationKey,
+    year: (Option<u32>, String),
+    title: String,
+    journal: String,
+}
+
+impl SortKeys {
+    pub fn new(reference: &Reference) -> Self {
+        let normalized = |text: &str| normalize_latex(text, false).as_string().trim().to_string();
+        let field = |name: &str| reference.fields.get(name).map(|value| normalized(value));
+
+        let year = normalize_latex(reference.year().map_or("", String::as_str), false);
+
+        Self {
+            key: normalized(&reference.key),
+            entry_type: reference.entry_type(),
+            collation: CollationKey::new(reference),
+            year: (leading_number(&year), year.as_string().trim().to_string()),
+            title: field("title").unwrap_or_default(),
+            journal: field("journal")
+                .or_else(|| field("booktitle"))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Compares the references with the given indices (which are their positions in the file) by their
+/// sort keys. References without a value come last in either direction, and references with the
+/// same value stay in the order of the file.
+pub fn compare(keys: &[SortKeys], order: SortOrder, a: usize, b: usize) -> Ordering {
+    let (x, y) = (&keys[a], &keys[b]);
+    let by_value = |x: &str, y: &str| {
+        x.is_empty()
+            .cmp(&y.is_empty())
+            .then_with(|| directed(x.cmp(y), order.ascending))
+    };
+
+    let ordering = match order.column {
+        SortColumn::Key => by_value(&x.key, &y.key),
+        SortColumn::EntryType => by_value(&x.entry_type, &y.entry_type),
+        SortColumn::Author => (!x.collation.has_author())
+            .cmp(&!y.collation.has_author())
+            .then_with(|| directed(x.collation.cmp(&y.collation), order.ascending)),
+        SortColumn::Year => x.year.0.is_none().cmp(&y.year.0.is_none()).then_with(|| {
+            directed(x.year.0.cmp(&y.year.0), order.ascending)
+                .then_with(|| by_value(&x.year.1, &y.year.1))
+        }),
+        SortColumn::Title => by_value(&x.title, &y.title),
+        SortColumn::Journal => by_value(&x.journal, &y.journal),
+        SortColumn::DateAdded => directed(a.cmp(&b), order.ascending),
+    };
+    ordering.then(a.cmp(&b))
+}
+
+fn directed(ordering: Ordering, ascending: bool) -> Ordering {
+    match ascending {
+        true => ordering,
+        false => ordering.reverse(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse::parse_bibtex;
+
+    fn sorted_keys(bibtex: &str, column: SortColumn, ascending: bool) -> Vec<String> {
+        let references = parse_bibtex(bibtex.to_string(), "test.bib").references;
+        let keys: Vec<SortKeys> = references.iter().map(SortKeys::new).collect();
+        let mut indices: Vec<usize> = (0..references.len()).collect();
+        let order = SortOrder { column, ascending };
+        indices.sort_by(|&a, &b| compare(&keys, order, a, b));
+        indices
+            .into_iter()
+            .map(|index| references[index].key.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_sort() {
+        let bibtex = "
+            @article{c, author = {{\\\"O}zil, Mesut}, year = {2010}, title = {{T}he Last}}
+            @article{a, author = {Bauer, Anna}, year = {1999a}, title = {An Article}, journal = {Zeta}}
+            @book{b, editor = {Abel, Karl}, title = {Collected}}
+            @article{d, author = {Bauer, Anna}, year = {2020}, journal = {Alpha}}
+        ";
+
+        assert_eq!(
+            vec!["b", "a", "d", "c"],
+            sorted_keys(bibtex, SortColumn::Author, true)
+        );
+        // References by the same author are sorted by year
+        assert_eq!(
+            vec!["c", "d", "a", "b"],
+            sorted_keys(bibtex, SortColumn::Author, false)
+        );
+        // Without a year, "b" comes last either way
+        assert_eq!(
+            vec!["a", "c", "d", "b"],
+            sorted_keys(bibtex, SortColumn::Year, true)
+        );
+        assert_eq!(
+            vec!["d", "c", "a", "b"],
+            sorted_keys(bibtex, SortColumn::Year, false)
+        );
+        assert_eq!(
+            vec!["a", "b", "c", "d"],
+            sorted_keys(bibtex, SortColumn::Title, true)
+        );
+        assert_eq!(
+            vec!["d", "a", "c", "b"],
+            sorted_keys(bibtex, SortColumn::Journal, true)
+        );
+        assert_eq!(
+            vec!["d", "b", "a", "c"],
+            sorted_keys(bibtex, SortColumn::DateAdded, false)
+        );
+    }
+
+    #[test]
+    fn test_collation() {
+        let bibtex = "
+            @book{beethoven, author = {Ludwig van Beethoven}, title = {Symphonies}}
+            @book{bach2, author = {Bach, Johann Sebastian}, year = 1722, title = {Well-Tempered Clavier}}
+            @book{bach1, author = {Bach, Johann Sebastian}, year = 1722, title = {Inventions}}
+            @book{anon, title = {Anonymous}}
+            @book{who, editor = {{\\'A}lvarez, Ana}, year = 2000}
+            @book{bach3, author = {Bach, Carl Philipp Emanuel}, year = 1753}
+        ";
+        let mut references = parse_bibtex(bibtex.to_string(), "test.bib").references;
+        references.sort_by_key(CollationKey::new);
+        let keys: Vec<&str> = references.iter().map(|r| r.key.as_str()).collect();
+        assert_eq!(
+            vec!["who", "bach3", "bach1", "bach2", "beethoven", "anon"],
+            keys
+        );
+    }
+
+    #[test]
+    fn test_sort_column() {
+        assert_eq!(Some(SortColumn::Year), SortColumn::from_column(3));
+        assert_eq!(None, SortColumn::from_column(5));
+        assert_eq!(SortColumn::Key, SortColumn::DateAdded.next());
+    }
+}
@@ -0,0 +1,537 @@
+use crate::{
+    latex,
+    reference::{Author, AuthorName, Reference},
+};
+
+/// A template for citing a reference as plain text, like `{author:last|join(", ")} ({year}). {title|decode}.`
+///
+/// - `{name}` is the field with that name as it is in the file, or `key`, `type` or `bibtex` (the whole entry,
+///   with the `@string` macros of the file expanded).
+///   `{author}` and `{editor}` are the names as they are shown in the table, and `{author:last}`,
+///   `{author:first}` and `{author:full}` are lists of the parts of the names, decoded from LaTeX.
+/// - A reference that lacks a field can't be cited, unless the field is optional, like `{doi?}`, or the
+///   text that needs it is in a conditional: `{if volume}, vol. {volume}{else}, in press{end}`.
+/// - Filters change values: `upper`, `lower`, `capitalize`, `initials` ("Ana Maria" becomes "A. M."),
+///   `truncate(n)` (to n characters, or n names), `decode` (from LaTeX to Unicode) and `join(separator)` or
+///   `join(separator, last separator)` for lists, which are otherwise joined with ", ". A list that ends
+///   with "and others", or was truncated, ends with "et al.", and then the last separator isn't used.
+/// - `{{` and `}}` are braces.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Value(Placeholder),
+    If {
+        name: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Placeholder {
+    name: String,
+    part: Option<NamePart>,
+    optional: bool,
+    filters: Vec<Filter>,
+}
+
+/// A conditional while it is parsed: the nodes around it, and those of the then branch once `{else}` is reached
+struct OpenIf {
+    name: String,
+    outer: Vec<Node>,
+    then: Option<Vec<Node>>,
+}
+
+/// A part of the names in the author or editor field
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NamePart {
+    /// The last name with particles, like "van Beethoven"
+    Last,
+    First,
+    Full,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Upper,
+    Lower,
+    Capitalize,
+    Initials,
+    Truncate(usize),
+    Decode,
+    Join(String, Option<String>),
+}
+
+/// What a placeholder stands for: a text, or a list of names, which may be followed by "and others"
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Text(String),
+    List { names: Vec<String>, others: bool },
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        // The nodes of the innermost conditional that is open, or of the template
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut open: Vec<OpenIf> = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => {
+                    return Err(String::from(
+                        "there is a } without a {. Use }} for a brace.",
+                    ))
+                }
+                '{' => {
+                    let tag = read_tag(&mut chars)?;
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    let tag = tag.trim();
+                    if let Some(name) = tag.strip_prefix("if ") {
+                        // Whether a field is there doesn't depend on which part of the names is used
+                        let name = name.split(':').next().unwrap_or_default();
+                        open.push(OpenIf {
+                            name: name.trim().to_string(),
+                            outer: std::mem::take(&mut nodes),
+                            then: None,
+                        });
+                    } else if tag == "else" {
+                        match open.last_mut() {
+                            Some(open_if) if open_if.then.is_none() => {
+                                open_if.then = Some(std::mem::take(&mut nodes));
+                            }
+                            _ => return Err(String::from("there is an {else} without an {if}.")),
+                        }
+                    } else if tag == "end" {
+                        let Some(open_if) = open.pop() else {
+                            return Err(String::from("there is an {end} without an {if}."));
+                        };
+                        let current = std::mem::replace(&mut nodes, open_if.outer);
+                        let (then, otherwise) = match open_if.then {
+                            Some(then) => (then, current),
+                            None => (current, Vec::new()),
+                        };
+                        nodes.push(Node::If {
+                            name: open_if.name,
+                            then,
+                            otherwise,
+                        });
+                    } else {
+                        nodes.push(Node::Value(parse_placeholder(tag)?));
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+        if let Some(open_if) = open.last() {
+            return Err(format!("the {{if {}}} has no {{end}}.", open_if.name));
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(Template { nodes })
+    }
+
+    /// The template filled in with the values of a reference, or why that's not possible
+    pub fn render(&self, reference: &Reference) -> Result<String, String> {
+        let mut rendered = String::new();
+        render_nodes(&self.nodes, reference, &mut rendered)?;
+        Ok(rendered)
+    }
+}
+
+/// Reads the text up to the `}` that closes a tag, skipping over quoted arguments
+fn read_tag(chars: &mut impl Iterator<Item = char>) -> Result<String, String> {
+    let mut tag = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in chars {
+        match c {
+            '}' if !quoted => return Ok(tag),
+            '"' if !escaped => quoted = !quoted,
+            _ => {}
+        }
+        escaped = c == '\\' && !escaped;
+        tag.push(c);
+    }
+    Err(format!("the {{{} is not closed.", tag))
+}
+
+/// Parses the inside of a placeholder, like `author:last|join(", ")`
+fn parse_placeholder(tag: &str) -> Result<Placeholder, String> {
+    let mut parts = split_outside_quotes(tag, '|').into_iter();
+    let value = parts.next().unwrap_or_default();
+    let (value, optional) = match value.trim().strip_suffix('?') {
+        Some(value) => (value.trim().to_string(), true),
+        None => (value.trim().to_string(), false),
+    };
+    let (name, part) = match value.split_once(':') {
+        Some((name, part)) => {
+            let part = match part.trim() {
+                "last" => NamePart::Last,
+                "first" => NamePart::First,
+                "full" => NamePart::Full,
+                other => return Err(format!("names have no part {}.", other)),
+            };
+            (name.trim().to_string(), Some(part))
+        }
+        None => (value, None),
+    };
+    if name.is_empty() {
+        return Err(String::from("there is a placeholder without a name."));
+    }
+    if part.is_some() && name != "author" && name != "editor" {
+        return Err(format!("only author and editor have parts, not {}.", name));
+    }
+    let filters = parts
+        .map(|filter| parse_filter(filter.trim()))
+        .collect::<Result<Vec<Filter>, String>>()?;
+    Ok(Placeholder {
+        name,
+        part,
+        optional,
+        filters,
+    })
+}
+
+fn parse_filter(filter: &str) -> Result<Filter, String> {
+    let (name, arguments) = match filter.split_once('(') {
+        Some((name, arguments)) => {
+            let Some(arguments) = arguments.trim_end().strip_suffix(')') else {
+                return Err(format!("the arguments of {} are not closed.", name.trim()));
+            };
+            let arguments: Vec<String> = split_outside_quotes(arguments, ',')
+                .iter()
+                .map(|argument| unquote(argument.trim()))
+                .collect();
+            (name.trim(), arguments)
+        }
+        None => (filter, Vec::new()),
+    };
+    let filter = match (name, arguments.as_slice()) {
+        ("upper", []) => Filter::Upper,
+        ("lower", []) => Filter::Lower,
+        ("capitalize", []) => Filter::Capitalize,
+        ("initials", []) => Filter::Initials,
+        ("decode", []) => Filter::Decode,
+        ("truncate", [length]) => match length.parse() {
+            Ok(length) => Filter::Truncate(length),
+            Err(_) => return Err(format!("truncate needs a number, not {}.", length)),
+        },
+        ("join", [separator]) => Filter::Join(separator.clone(), None),
+        ("join", [separator, last]) => Filter::Join(separator.clone(), Some(last.clone())),
+        ("upper" | "lower" | "capitalize" | "initials" | "decode", _) => {
+            return Err(format!("{} has no arguments.", name))
+        }
+        ("truncate", _) => return Err(String::from("truncate needs a length, like truncate(3).")),
+        ("join", _) => return Err(String::from("join needs a separator, like join(\", \").")),
+        (name, _) => return Err(format!("there is no filter {}.", name)),
+    };
+    Ok(filter)
+}
+
+/// Splits a text at a character, except where it is in quotes
+fn split_outside_quotes(text: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            '"' if !escaped => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        escaped = c == '\\' && !escaped;
+        if let Some(part) = parts.last_mut() {
+            part.push(c);
+        }
+    }
+    parts
+}
+
+/// The text in quotes, with escaped characters replaced, or the text itself if it isn't quoted
+pub fn unquote(value: &str) -> String {
+    let Some(quoted) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    else {
+        return value.to_string();
+    };
+    let mut unquoted = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => unquoted.push('\n'),
+                Some('t') => unquoted.push('\t'),
+                Some(other) => unquoted.push(other),
+                None => unquoted.push('\\'),
+            },
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    reference: &Reference,
+    rendered: &mut String,
+) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => rendered.push_str(text),
+            Node::Value(placeholder) => match value(reference, &placeholder.name, placeholder.part)
+            {
+                Some(value) => {
+                    let value = placeholder
+                        .filters
+                        .iter()
+                        .fold(value, |value, filter| apply_filter(filter, value));
+                    // Lists that weren't joined are joined with ", "
+                    if let Value::Text(text) =
+                        apply_filter(&Filter::Join(String::from(", "), None), value)
+                    {
+                        rendered.push_str(&text);
+                    }
+                }
+                None if placeholder.optional => {}
+                None => return Err(format!("{} has no {}.", reference.key, placeholder.name)),
+            },
+            Node::If {
+                name,
+                then,
+                otherwise,
+            } => match value(reference, name, None) {
+                Some(_) => render_nodes(then, reference, rendered)?,
+                None => render_nodes(otherwise, reference, rendered)?,
+            },
+        }
+    }
+    Ok(())
+}
+
+/// The value of a placeholder for a reference, if it has one that isn't empty
+fn value(reference: &Reference, name: &str, part: Option<NamePart>) -> Option<Value> {
+    let names = |names: Vec<Author>| -> Option<Value> {
+        let part = part?;
+        let others = names.iter().any(|name| name.name == AuthorName::Others);
+        let names: Vec<String> = names
+            .iter()
+            .filter(|name| name.name != AuthorName::Others)
+            .map(|name| name_part(name, part))
+            .collect();
+        (!names.is_empty()).then_some(Value::List { names, others })
+    };
+    let value = match name {
+        "key" => Value::Text(reference.key.clone()),
+        "type" => Value::Text(reference.entry_type()),
+        "bibtex" => Value::Text(reference.to_standalone_bibtex().trim_end().to_string()),
+        "author" if part.is_some() => names(reference.authors())?,
+        "editor" if part.is_some() => names(reference.editors())?,
+        "author" => Value::Text(reference.formatted_author()?),
+        "editor" => Value::Text(reference.formatted_editor()?),
+        name => Value::Text(reference.fields.get(name)?.clone()),
+    };
+    match &value {
+        Value::Text(text) if text.trim().is_empty() => None,
+        _ => Some(value),
+    }
+}
+
+fn name_part(author: &Author, part: NamePart) -> String {
+    match (&author.name, part) {
+        (_, NamePart::Full) => author.full_name(),
+        (AuthorName::Person { von, last, .. }, NamePart::Last) => {
+            let last = match von.is_empty() {
+                true => last.clone(),
+                false => format!("{} {}", von, last),
+            };
+            latex::decode(&last)
+        }
+        (AuthorName::Person { first, .. }, NamePart::First) => latex::decode(first),
+        (AuthorName::FullName(name), NamePart::Last) => latex::decode(name),
+        (AuthorName::FullName(_) | AuthorName::Others, _) => String::new(),
+    }
+}
+
+fn apply_filter(filter: &Filter, value: Value) -> Value {
+    match (filter, value) {
+        (Filter::Join(separator, last), Value::List { mut names, others }) => {
+            if others {
+                names.push(String::from("et al."));
+            }
+            let joined = match (last, names.pop()) {
+                (Some(last), Some(final_name)) if !others && !names.is_empty() => {
+                    format!("{}{}{}", names.join(separator), last, final_name)
+                }
+                (_, final_name) => {
+                    names.extend(final_name);
+                    names.join(separator)
+                }
+            };
+            Value::Text(joined)
+        }
+        (Filter::Join(..), text) => text,
+        (Filter::Truncate(length), Value::List { mut names, others }) => {
+            let others = others || names.len() > *length;
+            names.truncate(*length);
+            Value::List { names, others }
+        }
+        (filter, Value::Text(text)) => Value::Text(apply_text_filter(filter, &text)),
+        (filter, Value::List { names, others }) => Value::List {
+            names: names
+                .iter()
+                .map(|name| apply_text_filter(filter, name))
+                .collect(),
+            others,
+        },
+    }
+}
+
+fn apply_text_filter(filter: &Filter, text: &str) -> String {
+    match filter {
+        Filter::Upper => text.to_uppercase(),
+        Filter::Lower => text.to_lowercase(),
+        Filter::Capitalize => {
+            let mut chars = text.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        }
+        // "Jean-Paul Marie" becomes "J.-P. M."
+        Filter::Initials => text
+            .split_whitespace()
+            .map(|word| {
+                word.split('-')
+                    .filter_map(|part| part.chars().find(|c| c.is_alphanumeric()))
+                    .map(|initial| format!("{}.", initial))
+                    .collect::<Vec<String>>()
+                    .join("-")
+            })
+            .filter(|initials| !initials.is_empty())
+            .collect::<Vec<String>>()
+            .join(" "),
+        Filter::Truncate(length) => match text.chars().count() > *length {
+            true => format!(
+                "{}…",
+                text.chars().take(*length).collect::<String>().trim_end()
+            ),
+            false => text.to_string(),
+        },
+        Filter::Decode => latex::decode(text),
+        Filter::Join(..) => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse::parse_bibtex;
+
+    fn reference() -> Reference {
+        let bibtex = "@article{Beethoven1800, author = {Ludwig van Beethoven and Jean-Paul Sartre and Doe, Jane and others},
+            title = {{\\\"O}ber die {S}ymphonie}, year = 1800}";
+        parse_bibtex(bibtex.to_string(), "test.bib")
+            .references
+            .remove(0)
+    }
+
+    fn render(template: &str) -> Result<String, String> {
+        Template::parse(template)?.render(&reference())
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            Ok(String::from("van Beethoven, Sartre, Doe, et al. (1800)")),
+            render("{author:last|join(\", \", \" and \")} ({year})")
+        );
+        assert_eq!(
+            Ok(String::from("L., J.-P., J., et al.")),
+            render("{author:first|initials}")
+        );
+        assert_eq!(
+            Ok(String::from("Ludwig van Beethoven; et al.")),
+            render("{author:full|truncate(1)|join(\"; \")}")
+        );
+        assert_eq!(
+            Ok(String::from(
+                "ÖBER DIE SYMPHONIE / {\\\"O}ber die {S}ymphonie"
+            )),
+            render("{title|decode|upper} / {title}")
+        );
+        assert_eq!(
+            Ok(String::from("Öber die…")),
+            render("{title|decode|truncate(8)}")
+        );
+        assert_eq!(
+            Ok(String::from("{Beethoven1800} Article")),
+            render("{{{key}}} {type|capitalize}")
+        );
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        assert_eq!(
+            Err(String::from("Beethoven1800 has no journal.")),
+            render("{title}, {journal}")
+        );
+        assert_eq!(Ok(String::from("1800, ")), render("{year}, {journal?}"));
+        assert_eq!(
+            Ok(String::from("in press, 1800")),
+            render("{if journal}{journal}{if volume} {volume}{end}{else}in press{end}, {year}")
+        );
+        assert_eq!(Ok(String::from("yes")), render("{if author:last}yes{end}"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |template: &str| Template::parse(template).unwrap_err();
+        assert_eq!("the {year is not closed.", error("{year"));
+        assert_eq!(
+            "there is a } without a {. Use }} for a brace.",
+            error("year}")
+        );
+        assert_eq!(
+            "the {if volume} has no {end}.",
+            error("{if volume}{volume}")
+        );
+        assert_eq!("there is an {end} without an {if}.", error("{volume}{end}"));
+        assert_eq!(
+            "there is an {else} without an {if}.",
+            error("{if a}{else}{else}{end}")
+        );
+        assert_eq!(
+            "only author and editor have parts, not title.",
+            error("{title:last}")
+        );
+        assert_eq!(
+            "truncate needs a number, not many.",
+            error("{title|truncate(many)}")
+        );
+        assert_eq!("there is no filter shout.", error("{title|shout}"));
+        // Quoted arguments can contain the characters that end a placeholder
+        assert!(Template::parse("{author:last|join(\"} | \")}").is_ok());
+    }
+}
@@ -1,29 +1,72 @@
-use std::cmp::Ordering;
-
 use ratatui::{
-    layout::{Constraint, Layout, Margin, Rect},
-    style::{Modifier, Style, Stylize},
-    text::{Line, Text},
-    widgets::{Cell, HighlightSpacing, Paragraph, Row, Scrollbar, ScrollbarOrientation, Table},
+    layout::{Alignment, Constraint, Layout, Margin, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{
+        block::{Position, Title},
+        Block, Borders, Cell, Clear, HighlightSpacing, Paragraph, Row, Scrollbar,
+        ScrollbarOrientation, Table, Wrap,
+    },
     Frame,
 };
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
-    app::{StatusBar, StatusBarInput, ITEM_HEIGHT},
-    reference::Reference,
-    App,
+    app::{PasteBuffer, StatusBar, StatusBarInput, ITEM_HEIGHT},
+    diff::DiffLine,
+    editor::{EditedPart, FieldEditor, FIXED_ROWS},
+    latex, App,
 };
 
 pub fn ui(frame: &mut Frame, app: &mut App) {
-    let rects = Layout::vertical([Constraint::Min(5), Constraint::Length(1)]).split(frame.size());
+    let errors_height = if app.show_errors {
+        // Every error takes up three lines (message, snippet and caret), plus the borders
+        (app.errors.len() * 3 + 2).min(frame.size().height as usize / 2) as u16
+    } else {
+        0
+    };
+    let rects = Layout::vertical([
+        Constraint::Min(5),
+        Constraint::Length(errors_height),
+        Constraint::Length(1),
+    ])
+    .split(frame.size());
 
     app.set_colors();
 
-    render_table(frame, app, rects[0]);
+    let table_area = if app.show_details {
+        // Show the details next to the table on wide screens, and below it on narrow ones
+        let layout = if rects[0].width >= 120 {
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+        } else {
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+        };
+        let areas = layout.split(rects[0]);
+        render_details(frame, app, areas[1]);
+        areas[0]
+    } else {
+        rects[0]
+    };
+
+    render_table(frame, app, table_area);
+
+    render_scrollbar(frame, app, table_area);
+
+    if app.show_errors {
+        render_errors(frame, app, rects[1]);
+    }
 
-    render_scrollbar(frame, app, rects[0]);
+    render_footer(frame, app, rects[2]);
 
-    render_footer(frame, app, rects[1]);
+    if let Some(editor) = &app.editor {
+        render_editor(frame, app, editor, rects[0]);
+    }
+    if let Some(paste_buffer) = &app.paste_buffer {
+        render_paste_buffer(frame, app, paste_buffer, rects[0]);
+    }
+    if app.show_conflict_diff {
+        render_conflict_diff(frame, app, rects[0]);
+    }
 }
 
 fn render_table(frame: &mut Frame, app: &mut App, area: Rect) {
@@ -34,36 +77,74 @@ fn render_table(frame: &mut Frame, app: &mut App, area: Rect) {
         .add_modifier(Modifier::REVERSED)
         .fg(app.colors.selected_style_fg);
 
-    let header = ["Key", "Authors", "Year", "Title"]
+    // The column the table is sorted by has an arrow that shows the direction
+    let sorted_column = app.sort_order.column.column();
+    let arrow = if app.sort_order.ascending {
+        "▲"
+    } else {
+        "▼"
+    };
+    // The first column is a gutter for marks
+    let header = ["", "Key", "Type", "Authors", "Year", "Title"]
         .iter()
-        .cloned()
-        .map(Cell::from)
+        .enumerate()
+        .map(
+            |(column, &name)| match Some(column) == sorted_column.map(|c| c + 1) {
+                true => Cell::from(format!("{} {}", name, arrow)),
+                false => Cell::from(name),
+            },
+        )
         .collect::<Row>()
         .style(header_style)
         .height(1);
 
-    let items = &mut app.items;
-    items.sort_by(compare_authors);
+    let match_style = Style::new()
+        .fg(app.colors.selected_style_fg)
+        .add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
 
-    let rows = items.iter().enumerate().map(|(i, reference)| {
+    let marked = app.marked_with_visual_range();
+    let mark_style = Style::new()
+        .fg(app.colors.selected_style_fg)
+        .add_modifier(Modifier::BOLD);
+
+    let rows = app.view.iter().enumerate().map(|(i, &index)| {
         let color = match i % 2 {
             0 => app.colors.normal_row_color,
             _ => app.colors.alt_row_color,
         };
 
-        let is_search_result = app.search_results.contains(reference);
-        let row_style = if is_search_result {
+        let highlights = app.search_hits.get(&index);
+        let mut row_style = if highlights.is_some() {
             Style::new().fg(app.colors.search_result_fg).bg(color)
         } else {
             Style::new().fg(app.colors.row_fg).bg(color)
         };
+        // References with unsaved changes are in italics
+        if app.items[index].modified {
+            row_style = row_style.add_modifier(Modifier::ITALIC);
+        }
 
-        reference
-            .as_array()
+        let gutter = match marked.contains(&index) {
+            true => Cell::from(Span::styled("●", mark_style)),
+            false => Cell::from(""),
+        };
+        // The texts of the columns are kept with the search index, so they don't have to be formatted
+        // again on every frame
+        let columns = app.search_index.references[index]
+            .columns
             .iter()
-            .cloned()
-            .map(|content| content.unwrap_or_default())
-            .map(|content| Cell::from(Text::from(format!("{}", content))))
+            .enumerate()
+            .map(|(column, content)| match highlights {
+                // Highlight the characters that matched
+                Some(highlights) => Cell::from(Line::from(highlight(
+                    content,
+                    &highlights[column],
+                    match_style,
+                ))),
+                None => Cell::from(Text::from(content.as_str())),
+            });
+        std::iter::once(gutter)
+            .chain(columns)
             .collect::<Row>()
             .style(row_style)
             // Using unwrap() is fine here, because ITEM_HEIGHT is a constant
@@ -71,38 +152,48 @@ fn render_table(frame: &mut Frame, app: &mut App, area: Rect) {
     });
 
     let bar = " █ ";
-    let table = Table::new(
-        rows,
-        [
-            // + 1 is for padding.
-            // key
-            // This is somewhat arbitrary, but having the column be slightly less wide than to fit is fine for keys,
-            // since we normally don't need to see the entire key anyway.
-            Constraint::Min(app.longest_item_lens.0 - 6),
-            // For the author, we use a percentage, because the longest item is going to be like 300 characters
-            Constraint::Percentage(25),
-            // Years are almost always 4 digits long, so setting using `Length` to 6 is fine here.
-            // An exception to this is the format "1985 [1935]", which will not be entirely visible.
-            Constraint::Length(6),
-            // Let title take up the rest of the space
-            Constraint::Fill(1),
-        ],
-    )
-    .header(header)
-    .highlight_style(selected_style)
-    .highlight_symbol(Text::from(vec![
-        "".into(),
-        bar.into(),
-        bar.into(),
-        "".into(),
-    ]))
-    .bg(app.colors.buffer_bg)
-    .highlight_spacing(HighlightSpacing::Always);
-    frame.render_stateful_widget(table, area, &mut app.state);
-}
+    let widths = [
+        // marks
+        Constraint::Length(1),
+        // + 1 is for padding.
+        // key
+        // This is somewhat arbitrary, but having the column be slightly less wide than to fit is fine for keys,
+        // since we normally don't need to see the entire key anyway.
+        Constraint::Min(app.longest_item_lens.0.saturating_sub(6)),
+        // entry type
+        Constraint::Length(app.longest_item_lens.1 + 1),
+        // For the author, we use a percentage, because the longest item is going to be like 300 characters
+        Constraint::Percentage(25),
+        // Years are almost always 4 digits long, so setting using `Length` to 6 is fine here.
+        // An exception to this is the format "1985 [1935]", which will not be entirely visible.
+        Constraint::Length(6),
+        // Let title take up the rest of the space
+        Constraint::Fill(1),
+    ];
 
-fn compare_authors(a: &Reference, b: &Reference) -> Ordering {
-    a.formatted_author().cmp(&b.formatted_author())
+    // Remember where the headers are, so that clicking on them sorts the table. The columns are laid out
+    // as the table does it, after the space for the highlight symbol.
+    let symbol_width = bar.width() as u16;
+    let columns_area = Rect {
+        x: area.x + symbol_width.min(area.width),
+        width: area.width.saturating_sub(symbol_width),
+        height: 1,
+        ..area
+    };
+    app.header_columns = Layout::horizontal(widths).spacing(1).split(columns_area)[1..].to_vec();
+
+    let table = Table::new(rows, widths)
+        .header(header)
+        .highlight_style(selected_style)
+        .highlight_symbol(Text::from(vec![
+            "".into(),
+            bar.into(),
+            bar.into(),
+            "".into(),
+        ]))
+        .bg(app.colors.buffer_bg)
+        .highlight_spacing(HighlightSpacing::Always);
+    frame.render_stateful_widget(table, area, &mut app.state);
 }
 
 fn render_scrollbar(frame: &mut Frame, app: &mut App, area: Rect) {
@@ -119,15 +210,401 @@ fn render_scrollbar(frame: &mut Frame, app: &mut App, area: Rect) {
     );
 }
 
+fn render_details(frame: &mut Frame, app: &mut App, area: Rect) {
+    let Some(reference) = app.selected_reference() else {
+        return;
+    };
+    let label_style = Style::new()
+        .fg(app.colors.selected_style_fg)
+        .add_modifier(Modifier::BOLD);
+    let highlight_style = Style::new()
+        .fg(app.colors.buffer_bg)
+        .bg(app.colors.search_result_fg);
+    let label = |name: &str| Span::styled(format!("{}: ", name), label_style);
+    let highlighted = |text: &str| {
+        let positions = match &app.search_query {
+            Some(query) => query.highlight(text, app.stem),
+            None => Vec::new(),
+        };
+        highlight(text, &positions, highlight_style)
+    };
+
+    let mut lines: Vec<Line> = vec![Line::from(vec![
+        label("type"),
+        Span::raw(reference.entry_type()),
+    ])];
+
+    for (name, people) in [
+        ("author", reference.authors()),
+        ("editor", reference.editors()),
+    ] {
+        if people.is_empty() {
+            continue;
+        }
+        lines.push(Line::from(label(name)));
+        for person in people {
+            let mut spans = vec![Span::raw("  • ")];
+            spans.extend(highlighted(&person.full_name()));
+            lines.push(Line::from(spans));
+        }
+    }
+
+    // Authors and editors are listed above, and the abstract goes last because it is usually the longest
+    let is_listed_separately = |name: &str| {
+        ["author", "editor", "abstract"]
+            .iter()
+            .any(|listed| name.eq_ignore_ascii_case(listed))
+    };
+    for field in reference
+        .fields
+        .iter()
+        .filter(|field| !is_listed_separately(&field.name))
+    {
+        let mut spans = vec![label(&field.name)];
+        spans.extend(highlighted(&latex::decode(&field.value)));
+        lines.push(Line::from(spans));
+    }
+
+    if let Some(abstract_field) = reference.fields.get_field("abstract") {
+        lines.push(Line::default());
+        lines.push(Line::from(label(&abstract_field.name)));
+        lines.push(Line::from(highlighted(&latex::decode(
+            &abstract_field.value,
+        ))));
+    }
+
+    // Don't scroll past the end of the (wrapped) text
+    let inner_width = area.width.saturating_sub(2).max(1) as usize;
+    let wrapped_height: usize = lines
+        .iter()
+        .map(|line| line.width().max(1).div_ceil(inner_width))
+        .sum();
+    let max_scroll = wrapped_height.saturating_sub(area.height.saturating_sub(2) as usize);
+    let scroll = app.details_scroll.min(max_scroll as u16);
+
+    let details = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .scroll((scroll, 0))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" {} ", reference.key)),
+        )
+        .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg));
+    frame.render_widget(details, area);
+    app.details_scroll = scroll;
+}
+
+/// Splits text into spans, giving the characters at the given (sorted) indices the highlight style.
+fn highlight(text: &str, positions: &[usize], highlight_style: Style) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    let mut run_is_highlighted = false;
+    let mut positions = positions.iter().peekable();
+    for (i, c) in text.chars().enumerate() {
+        let is_highlighted = positions.next_if_eq(&&i).is_some();
+        if is_highlighted != run_is_highlighted && !run.is_empty() {
+            spans.push(styled_run(
+                std::mem::take(&mut run),
+                run_is_highlighted,
+                highlight_style,
+            ));
+        }
+        run_is_highlighted = is_highlighted;
+        run.push(c);
+    }
+    spans.push(styled_run(run, run_is_highlighted, highlight_style));
+
+    spans
+}
+
+fn styled_run(run: String, is_highlighted: bool, highlight_style: Style) -> Span<'static> {
+    match is_highlighted {
+        true => Span::styled(run, highlight_style),
+        false => Span::raw(run),
+    }
+}
+
+fn render_errors(frame: &mut Frame, app: &mut App, area: Rect) {
+    let lines: Vec<Line> = app
+        .errors
+        .iter()
+        .flat_map(|error| {
+            error
+                .to_string()
+                .lines()
+                .map(|line| Line::from(line.to_string()))
+                .collect::<Vec<Line>>()
+        })
+        .collect();
+
+    // Don't scroll past the last error
+    let max_scroll = lines
+        .len()
+        .saturating_sub(area.height.saturating_sub(2) as usize);
+    let scroll = app.errors_scroll.min(max_scroll as u16);
+
+    let errors = Paragraph::new(lines)
+        .scroll((scroll, 0))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" Parse errors ({}) ", app.errors.len())),
+        )
+        .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg));
+    frame.render_widget(errors, area);
+    app.errors_scroll = scroll;
+}
+
 fn render_footer(frame: &mut Frame, app: &mut App, area: Rect) {
     let text = match &app.status_bar {
         StatusBar::Message(message) => message,
         StatusBar::Input(status_bar_input) => &status_bar_input.input,
     };
 
-    let footer = Paragraph::new(Line::from(format!("\n   {}", text)))
-        .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg));
+    let style = Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg);
+    let footer = Paragraph::new(Line::from(format!("\n   {}", text))).style(style);
     frame.render_widget(footer, area);
+
+    // Show how many references have unsaved changes, and how many are left when filtering, like "4/283"
+    let mut counts = String::new();
+    let modified_count = app.modified_count();
+    if modified_count > 0 {
+        counts.push_str(&format!("{} modified   ", modified_count));
+    }
+    let marked_count = app.marked_with_visual_range().len();
+    if marked_count > 0 {
+        counts.push_str(&format!("{} marked   ", marked_count));
+    }
+    if app.filtered {
+        counts.push_str(&format!("{}/{}   ", app.view.len(), app.items.len()));
+    }
+    if !counts.is_empty() {
+        let count = counts;
+        let count_area = Rect {
+            x: area.right().saturating_sub(count.len() as u16),
+            width: (count.len() as u16).min(area.width),
+            ..area
+        };
+        frame.render_widget(Paragraph::new(count).style(style), count_area);
+    }
+}
+
+/// Draws the editor over the middle of the screen: the name and value of every row, with values that
+/// span several lines on several lines, and a cursor in the text that is being typed.
+fn render_editor(frame: &mut Frame, app: &App, editor: &FieldEditor, area: Rect) {
+    let area = popup_area(area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" Edit {} ", app.items[editor.index].key));
+    let inner = block.inner(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        block.style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg)),
+        area,
+    );
+    let [form_area, help_area] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(inner);
+
+    // The name or value that is being typed is shown instead of the one in the form
+    let rows: Vec<(&str, &str)> = editor
+        .rows()
+        .into_iter()
+        .enumerate()
+        .map(|(row, (name, value))| match &editor.input {
+            Some((EditedPart::Name, input)) if row == editor.selected => {
+                (input.text.as_str(), value)
+            }
+            Some((EditedPart::Value, input)) if row == editor.selected => {
+                (name, input.text.as_str())
+            }
+            _ => (name, value),
+        })
+        .collect();
+    let name_width = rows.iter().map(|(name, _)| name.width()).max().unwrap_or(0) + 2;
+    let value_width = (form_area.width as usize).saturating_sub(name_width).max(1);
+
+    let label_style = Style::new()
+        .fg(app.colors.selected_style_fg)
+        .add_modifier(Modifier::BOLD);
+    let selected_style = Style::new().bg(app.colors.alt_row_color);
+    let mut lines: Vec<Line> = Vec::new();
+    // The line and column of the cursor, and the last line of the selected row
+    let mut cursor: Option<(usize, usize)> = None;
+    let mut selected_line = 0;
+    for (row, (name, value)) in rows.into_iter().enumerate() {
+        let wrapped = wrap(value, value_width);
+        if row == editor.selected {
+            cursor = match &editor.input {
+                Some((EditedPart::Name, input)) => {
+                    let before: String = input.text.chars().take(input.cursor).collect();
+                    Some((lines.len(), before.width()))
+                }
+                Some((EditedPart::Value, input)) => {
+                    let (line, column) = wrapped_cursor(&wrapped, input.cursor);
+                    Some((lines.len() + line, name_width + column))
+                }
+                None => None,
+            };
+            selected_line = lines.len() + wrapped.len() - 1;
+        }
+
+        // The entry type and key are set apart from the fields
+        let style = match row {
+            _ if row == editor.selected => selected_style,
+            row if row < FIXED_ROWS => Style::new().add_modifier(Modifier::ITALIC),
+            _ => Style::new(),
+        };
+        for (i, (_, text)) in wrapped.into_iter().enumerate() {
+            let label = match i {
+                0 => format!("{}{}", name, " ".repeat(name_width - name.width())),
+                _ => " ".repeat(name_width),
+            };
+            lines.push(
+                Line::from(vec![Span::styled(label, label_style), Span::raw(text)]).style(style),
+            );
+        }
+    }
+
+    // Scroll so that the selected row, or the cursor, is visible
+    let focus = cursor.map_or(selected_line, |(line, _)| line);
+    let scroll = (focus + 1).saturating_sub(form_area.height as usize);
+    frame.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), form_area);
+    if let Some((line, column)) = cursor {
+        frame.set_cursor(
+            form_area.x + (column as u16).min(form_area.width.saturating_sub(1)),
+            form_area.y + (line - scroll) as u16,
+        );
+    }
+
+    let help = match (&editor.error, &editor.input) {
+        (Some(error), None) => Line::styled(error.as_str(), Style::new().fg(Color::Red)),
+        (_, Some((_, input))) if input.multiline => {
+            Line::from("Enter: new line   Esc: done   Ctrl-s: save")
+        }
+        (_, Some(_)) => Line::from("Enter: done   Ctrl-s: save"),
+        (None, None) => Line::from(
+            "i: edit   r: rename   a: add field   d: delete field   w: save   q: discard changes",
+        ),
+    };
+    frame.render_widget(Paragraph::new(help), help_area);
+}
+
+/// Draws the text box for BibTeX entries to add over the middle of the screen, with the cursor in it.
+fn render_paste_buffer(frame: &mut Frame, app: &App, paste_buffer: &PasteBuffer, area: Rect) {
+    let area = popup_area(area);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(" Add BibTeX entries ");
+    let inner = block.inner(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        block.style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg)),
+        area,
+    );
+    let [text_area, help_area] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(inner);
+
+    let input = &paste_buffer.input;
+    let wrapped = wrap(&input.text, (text_area.width as usize).max(1));
+    let (line, column) = wrapped_cursor(&wrapped, input.cursor);
+    let scroll = (line + 1).saturating_sub(text_area.height as usize);
+    let lines: Vec<Line> = wrapped
+        .into_iter()
+        .map(|(_, text)| Line::from(text))
+        .collect();
+    frame.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), text_area);
+    frame.set_cursor(
+        text_area.x + (column as u16).min(text_area.width.saturating_sub(1)),
+        text_area.y + (line - scroll) as u16,
+    );
+
+    let help = match &paste_buffer.error {
+        Some(error) => Line::styled(error.as_str(), Style::new().fg(Color::Red)),
+        None => Line::from("Paste or type entries.   Ctrl-s: add   Esc: cancel"),
+    };
+    frame.render_widget(Paragraph::new(help), help_area);
+}
+
+/// Draws how the file was changed by another program since it was read, over the middle of the screen.
+fn render_conflict_diff(frame: &mut Frame, app: &mut App, area: Rect) {
+    let lines: Vec<Line> = app
+        .conflict_diff
+        .iter()
+        .map(|line| match line {
+            Some(DiffLine::Unchanged(text)) => Line::from(format!("  {}", text)),
+            Some(DiffLine::Removed(text)) => {
+                Line::styled(format!("- {}", text), Style::new().fg(Color::Red))
+            }
+            Some(DiffLine::Added(text)) => {
+                Line::styled(format!("+ {}", text), Style::new().fg(Color::Green))
+            }
+            None => Line::styled("  …", Style::new().add_modifier(Modifier::DIM)),
+        })
+        .collect();
+
+    let area = popup_area(area);
+    let max_scroll = lines
+        .len()
+        .saturating_sub(area.height.saturating_sub(2) as usize);
+    app.conflict_diff_scroll = app.conflict_diff_scroll.min(max_scroll as u16);
+    let diff_view = Paragraph::new(lines)
+        .scroll((app.conflict_diff_scroll, 0))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Changes to the file since it was read ")
+                .title(
+                    Title::from(" j/k: scroll   d: hide ")
+                        .position(Position::Bottom)
+                        .alignment(Alignment::Right),
+                ),
+        )
+        .style(Style::new().fg(app.colors.row_fg).bg(app.colors.buffer_bg));
+    frame.render_widget(Clear, area);
+    frame.render_widget(diff_view, area);
+}
+
+/// The area of a window over the middle of the screen
+fn popup_area(area: Rect) -> Rect {
+    Rect {
+        x: area.x + area.width / 10,
+        y: area.y + area.height / 10,
+        width: area.width - area.width / 5,
+        height: area.height - area.height / 5,
+    }
+}
+
+/// The line of wrapped text that a cursor (in characters) is on, and its column on the screen
+fn wrapped_cursor(wrapped: &[(usize, String)], cursor: usize) -> (usize, usize) {
+    let line = wrapped
+        .iter()
+        .rposition(|(start, _)| *start <= cursor)
+        .unwrap_or(0);
+    let (start, text) = &wrapped[line];
+    let before: String = text.chars().take(cursor - start).collect();
+    (line, before.width())
+}
+
+/// Splits text into lines at newlines and wherever a line is wider than `width`, with the position
+/// (in characters) in the text where every line starts.
+fn wrap(text: &str, width: usize) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for line in text.split('\n') {
+        let mut current = String::new();
+        let mut current_start = start;
+        for (i, c) in line.chars().enumerate() {
+            if current.width() + c.to_string().width() > width && !current.is_empty() {
+                lines.push((current_start, std::mem::take(&mut current)));
+                current_start = start + i;
+            }
+            current.push(c);
+        }
+        lines.push((current_start, current));
+        start += line.chars().count() + 1;
+    }
+    lines
 }
 
 pub fn move_cursor_left(status_bar_input: &StatusBarInput, cursor_position: usize) -> usize {
@@ -138,24 +615,28 @@ pub fn move_cursor_left(status_bar_input: &StatusBarInput, cursor_position: usiz
 pub fn move_cursor_right(status_bar_input: &StatusBarInput, cursor_position: usize) -> usize {
     let cursor_moved_right = cursor_position.saturating_add(1);
     //println!("position after add: {}", cursor_moved_right);
-    let nexty = clamp_cursor(status_bar_input, cursor_moved_right);
-    //println!("position after clamping: {}", nexty);
-    nexty
+    clamp_cursor(status_bar_input, cursor_moved_right)
 }
 
 pub fn enter_char(status_bar_input: &StatusBarInput, new_char: char) -> StatusBarInput {
     //println!("**current: {}", status_bar_input.cursor_position);
     let mut next_input: String = status_bar_input.input.clone();
-    next_input.insert(status_bar_input.cursor_position, new_char);
+    // The cursor counts characters, but a string is indexed by bytes
+    let byte_index = next_input
+        .char_indices()
+        .nth(status_bar_input.cursor_position)
+        .map_or(next_input.len(), |(index, _)| index);
+    next_input.insert(byte_index, new_char);
 
-    let next_cursor_position =
-        move_cursor_right(&status_bar_input, status_bar_input.cursor_position);
-    //println!("**next: {}**", next_cursor_position);
-    let sbi = StatusBarInput {
-        cursor_position: next_cursor_position,
+    let next_input = StatusBarInput {
+        cursor_position: status_bar_input.cursor_position,
         input: next_input,
     };
-    sbi
+    // The cursor is clamped to the input with the new character, so that it can move past it
+    StatusBarInput {
+        cursor_position: move_cursor_right(&next_input, next_input.cursor_position),
+        ..next_input
+    }
 }
 
 pub fn delete_char(status_bar_input: &StatusBarInput) -> StatusBarInput {
@@ -179,19 +660,59 @@ pub fn delete_char(status_bar_input: &StatusBarInput) -> StatusBarInput {
         // Put all characters together except the selected one.
         // By leaving the selected one out, it is forgotten and therefore deleted.
         let next_input: String = before_char_to_delete.chain(after_char_to_delete).collect();
-        let next_cursor_position = move_cursor_left(&status_bar_input, current_index);
-        let next_status_bar_input = StatusBarInput {
+        let next_cursor_position = move_cursor_left(status_bar_input, current_index);
+        StatusBarInput {
             cursor_position: next_cursor_position,
             input: next_input,
-        };
-        next_status_bar_input
+        }
     } else {
         status_bar_input.clone()
     }
 }
 
 pub fn clamp_cursor(status_bar_input: &StatusBarInput, new_cursor_pos: usize) -> usize {
-    let len = status_bar_input.input.len();
-    //println!("len of status bar input: {}", len);
-    new_cursor_pos.clamp(0, status_bar_input.input.len() + 1)
+    new_cursor_pos.clamp(0, status_bar_input.input.chars().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(mut input: StatusBarInput, text: &str) -> StatusBarInput {
+        for c in text.chars() {
+            input = enter_char(&input, c);
+        }
+        input
+    }
+
+    #[test]
+    fn test_enter_and_delete_non_ascii_characters() {
+        let prompt = StatusBarInput {
+            input: String::from("/"),
+            cursor_position: 1,
+        };
+        let input = type_text(prompt, "Sözeri");
+        assert_eq!("/Sözeri", input.input);
+        assert_eq!(7, input.cursor_position);
+
+        // Typing and deleting in the middle, before and after the 'ö'
+        let input = delete_char(&delete_char(&input));
+        assert_eq!("/Söze", input.input);
+        let input = StatusBarInput {
+            cursor_position: 3,
+            ..input
+        };
+        let input = type_text(input, "ü");
+        assert_eq!("/Söüze", input.input);
+        assert_eq!(4, input.cursor_position);
+        let input = delete_char(&delete_char(&input));
+        assert_eq!("/Sze", input.input);
+        assert_eq!(2, input.cursor_position);
+
+        // The cursor doesn't move past the end of the input, or into the prompt
+        assert_eq!(4, move_cursor_right(&input, 4));
+        let input = delete_char(&delete_char(&input));
+        assert_eq!("/ze", input.input);
+        assert_eq!(1, input.cursor_position);
+    }
 }
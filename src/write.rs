@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{parse::Block, reference::Reference};
+
+/// The file a bibliography was read from, with what it contained, so that changes can be written back into it.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    /// The blocks the file was parsed into, which [`Reference::origin`] points into
+    pub blocks: Vec<Block>,
+    /// The macros defined with `@string` in the file, as (name, unexpanded value) pairs
+    pub strings: Vec<(String, String)>,
+    /// The contents of the file when it was read (or last written), to notice if it was changed since
+    pub contents: String,
+}
+
+/// Writes references back into the file they were parsed from.
+///
+/// Everything that is not a reference is written exactly as it was read, and so are references that
+/// have not been modified, so writing an unchanged bibliography reproduces the original file byte for byte.
+/// Modified references are re-serialized in place, references that are no longer present are left out,
+/// and new references (without an origin) are appended at the end of the file.
+pub fn write_bibtex(blocks: &[Block], references: &[Reference]) -> String {
+    let references_by_origin: HashMap<usize, &Reference> = references
+        .iter()
+        .filter_map(|reference| Some((reference.origin?, reference)))
+        .collect();
+
+    let mut bibtex = String::new();
+    let mut skip_whitespace = false;
+
+    for (index, block) in blocks.iter().enumerate() {
+        match block {
+            Block::Verbatim(text) => {
+                if !(skip_whitespace && text.trim().is_empty()) {
+                    bibtex.push_str(text);
+                }
+                skip_whitespace = false;
+            }
+            Block::Entry(text) => match references_by_origin.get(&index) {
+                Some(reference) if reference.modified => {
+                    bibtex.push_str(reference.to_bibtex().trim_end())
+                }
+                Some(_) => bibtex.push_str(text),
+                // The reference was removed. To not leave an extra blank line behind, we also remove
+                // the whitespace before it, or after it if it is the first entry in the file.
+                None => match index
+                    .checked_sub(1)
+                    .and_then(|previous| blocks.get(previous))
+                {
+                    Some(Block::Verbatim(previous))
+                        if previous.trim().is_empty() && bibtex.ends_with(previous.as_str()) =>
+                    {
+                        bibtex.truncate(bibtex.len() - previous.len())
+                    }
+                    _ => skip_whitespace = true,
+                },
+            },
+        }
+    }
+
+    for reference in references
+        .iter()
+        .filter(|reference| reference.origin.is_none())
+    {
+        if !bibtex.is_empty() && !bibtex.ends_with("\n\n") {
+            bibtex.push_str(if bibtex.ends_with('\n') { "\n" } else { "\n\n" });
+        }
+        bibtex.push_str(&reference.to_bibtex());
+    }
+
+    bibtex
+}
+
+/// Writes `contents` to the file at `path` so that it is never left half-written: they are written to a
+/// temporary file next to it, which then replaces it.
+///
+/// If `backups` is given, the file as it was is first copied into a directory for it in that directory, where
+/// only the given number of most recent backups of it are kept.
+pub fn save_bibtex(path: &Path, contents: &str, backups: Option<(&Path, usize)>) -> io::Result<()> {
+    if let Some((directory, count)) = backups {
+        if count > 0 && path.exists() {
+            back_up(path, directory, count)?;
+        }
+    }
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let temporary_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    let write = || {
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temporary_path, metadata.permissions())?;
+        }
+        fs::rename(&temporary_path, path)
+    };
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+/// Copies the file into its [`backup_directory`] as `<file name>.<timestamp>`, or with a number added to that
+/// (`-001`) if the file was already backed up in the same second, and removes all but the `count` most recent
+/// of those copies.
+fn back_up(path: &Path, directory: &Path, count: usize) -> io::Result<()> {
+    let directory = backup_directory(directory, path)?;
+    fs::create_dir_all(&directory)?;
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let backup_name = format!("{}.{}", file_name, timestamp(SystemTime::now()));
+    let mut original = fs::File::open(path)?;
+    for number in 0.. {
+        let backup_path = match number {
+            0 => directory.join(&backup_name),
+            number => directory.join(format!("{}-{:03}", backup_name, number)),
+        };
+        // A backup is never overwritten, even by another program saving the file at the same time
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup_path)
+        {
+            Ok(mut backup) => {
+                io::copy(&mut original, &mut backup)?;
+                break;
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+
+    let prefix = format!("{}.", file_name);
+    let mut backups: Vec<PathBuf> = fs::read_dir(&directory)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|backup| {
+            let name = backup.file_name().unwrap_or_default().to_string_lossy();
+            name.strip_prefix(&prefix).is_some_and(is_backup_suffix)
+        })
+        .collect();
+    // The timestamps, and the numbers of backups made in the same second, sort in chronological order
+    backups.sort();
+    for backup in &backups[..backups.len().saturating_sub(count)] {
+        fs::remove_file(backup)?;
+    }
+    Ok(())
+}
+
+/// Whether what follows `<file name>.` in a file name is what [`back_up`] puts there: a timestamp, which
+/// may be followed by a number
+fn is_backup_suffix(suffix: &str) -> bool {
+    let (Some(timestamp), Some(number)) = (
+        suffix.get(..TIMESTAMP_LENGTH),
+        suffix.get(TIMESTAMP_LENGTH..),
+    ) else {
+        return false;
+    };
+    !timestamp.contains('.')
+        && (number.is_empty()
+            || number
+                .strip_prefix('-')
+                .is_some_and(|digits| digits.bytes().all(|b| b.is_ascii_digit())))
+}
+
+/// The directory in `directory` where the backups of the file at `path` are kept. Every file has its own, named
+/// after its full path with the separators replaced by '%' (like Vim names its swap files), so that files with
+/// the same name in different directories don't share their backups.
+fn backup_directory(directory: &Path, path: &Path) -> io::Result<PathBuf> {
+    let name: String = fs::canonicalize(path)?
+        .to_string_lossy()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '%',
+            c => c,
+        })
+        .collect();
+    Ok(directory.join(name))
+}
+
+/// The length of a [`timestamp`], like "2024-02-29_12-34-56"
+const TIMESTAMP_LENGTH: usize = 19;
+
+/// Formats a time as a UTC date and time that can be used in file names.
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, time_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Converts days since 1970-01-01 into a date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{fields::Field, parse::parse_bibtex};
+
+    fn assert_round_trip(bibtex: &str) {
+        let bibliography = parse_bibtex(bibtex.to_string(), "test.bib");
+        assert_eq!(
+            bibtex,
+            write_bibtex(&bibliography.blocks, &bibliography.references)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip(include_str!("../test_bibliography.bib"));
+        assert_round_trip(include_str!("../test_bibliography_small.bib"));
+        assert_round_trip(include_str!("../test_bibliography_exports.bib"));
+        // Macros, comments and malformed entries are kept as they are
+        assert_round_trip(
+            "% leading comment\n@string{jasa = \"JASA\"}\n@article{a, journal = jasa}\n\n@article{broken, title = {x}\n\n@misc(b, note = \"Trailing\") ",
+        );
+        assert_round_trip("");
+        assert_round_trip("@article{no_trailing_newline, title = {x}}");
+    }
+
+    #[test]
+    fn test_write_modified_references() {
+        let bibtex = "@article{a,\n  title = {First},\n}\n\n@book{b,\n  title   = {Second},\n}\n\n@misc{c,\n  title = {Third},\n}\n";
+        let mut bibliography = parse_bibtex(bibtex.to_string(), "test.bib");
+
+        // Modify b, remove c and add d
+        let b = &mut bibliography.references[1];
+        b.fields.insert(Field {
+            name: String::from("title"),
+            value: String::from("Changed"),
+            raw_value: None,
+        });
+        b.modified = true;
+        bibliography.references.remove(2);
+        let mut d = bibliography.references[0].clone();
+        d.key = String::from("d");
+        d.origin = None;
+        bibliography.references.push(d);
+
+        assert_eq!(
+            "@article{a,\n  title = {First},\n}\n\n@book{b,\n    title = {Changed},\n}\n\n@article{d,\n    title = {First},\n}\n",
+            write_bibtex(&bibliography.blocks, &bibliography.references)
+        );
+
+        // Removing the first entry
+        bibliography.references.remove(0);
+        assert!(
+            write_bibtex(&bibliography.blocks, &bibliography.references).starts_with("@book{b,\n")
+        );
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let at = |seconds| timestamp(UNIX_EPOCH + std::time::Duration::from_secs(seconds));
+        assert_eq!("1970-01-01_00-00-00", at(0));
+        assert_eq!("2000-03-01_00-00-00", at(951868800));
+        assert_eq!("2024-02-29_12-34-56", at(1709210096));
+        assert_eq!(TIMESTAMP_LENGTH, at(1709210096).len());
+    }
+
+    #[test]
+    fn test_save_bibtex_keeps_backups() {
+        let directory = std::env::temp_dir().join(format!("citeseer-save-{}", std::process::id()));
+        let backups = directory.join("backups");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("references.bib");
+        fs::write(&path, "original").unwrap();
+        // An old backup, and a file that isn't one
+        let file_backups = backup_directory(&backups, &path).unwrap();
+        fs::create_dir_all(&file_backups).unwrap();
+        fs::write(
+            file_backups.join("references.bib.2001-01-01_00-00-00"),
+            "old",
+        )
+        .unwrap();
+        fs::write(file_backups.join("references.bib.notes"), "keep").unwrap();
+
+        save_bibtex(&path, "changed", Some((&backups, 1))).unwrap();
+
+        assert_eq!("changed", fs::read_to_string(&path).unwrap());
+        let names = |directory: &Path| -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(directory)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        let backup_names = names(&file_backups);
+        assert_eq!(2, backup_names.len());
+        assert!(backup_names[0].starts_with("references.bib.20"));
+        assert_eq!("references.bib.notes", backup_names[1]);
+        assert_eq!(
+            "original",
+            fs::read_to_string(file_backups.join(&backup_names[0])).unwrap()
+        );
+        // No temporary file is left behind
+        assert_eq!(2, fs::read_dir(&directory).unwrap().count());
+
+        // Saving again in the same second doesn't overwrite the backup
+        save_bibtex(&path, "changed again", Some((&backups, 3))).unwrap();
+        save_bibtex(&path, "changed once more", Some((&backups, 3))).unwrap();
+        let backup_contents: Vec<String> = names(&file_backups)
+            .iter()
+            .filter(|name| *name != "references.bib.notes")
+            .map(|name| fs::read_to_string(file_backups.join(name)).unwrap())
+            .collect();
+        assert_eq!(
+            vec!["original", "changed", "changed again"],
+            backup_contents
+        );
+
+        // A file with the same name in another directory has its own backups
+        let other_path = directory.join("other").join("references.bib");
+        fs::create_dir_all(other_path.parent().unwrap()).unwrap();
+        fs::write(&other_path, "other").unwrap();
+        save_bibtex(&other_path, "other changed", Some((&backups, 1))).unwrap();
+        let other_backups = backup_directory(&backups, &other_path).unwrap();
+        assert_ne!(file_backups, other_backups);
+        assert_eq!(1, names(&other_backups).len());
+        assert_eq!(4, names(&file_backups).len());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
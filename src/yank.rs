@@ -0,0 +1,258 @@
+use crate::{
+    reference::Reference,
+    template::{unquote, Template},
+};
+
+/// A way of copying references to the clipboard, chosen with the key that is pressed after `y`.
+///
+/// The template is filled in for every reference, and the results are joined with the separator, between
+/// the prefix and the suffix, so that several references can be cited at once, like `\cite{a,b,c}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YankFormat {
+    pub key: char,
+    pub name: String,
+    pub prefix: String,
+    pub template: Template,
+    pub separator: String,
+    pub suffix: String,
+}
+
+impl YankFormat {
+    fn new(
+        key: char,
+        name: &str,
+        prefix: &str,
+        template: &str,
+        separator: &str,
+        suffix: &str,
+    ) -> Self {
+        Self {
+            key,
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            template: Template::parse(template).expect("the default templates are valid"),
+            separator: separator.to_string(),
+            suffix: suffix.to_string(),
+        }
+    }
+
+    /// The text to copy for the references, or why it can't be made
+    pub fn apply(&self, references: &[&Reference]) -> Result<String, String> {
+        let expanded = references
+            .iter()
+            .map(|reference| self.template.render(reference))
+            .collect::<Result<Vec<String>, String>>()?;
+        Ok(format!(
+            "{}{}{}",
+            self.prefix,
+            expanded.join(&self.separator),
+            self.suffix
+        ))
+    }
+}
+
+/// The formats that are available without configuring any
+pub fn default_formats() -> Vec<YankFormat> {
+    vec![
+        YankFormat::new('y', "BibTeX", "", "{bibtex}", "\n\n", "\n"),
+        YankFormat::new('k', "key", "", "{key}", ", ", ""),
+        YankFormat::new('c', "LaTeX citation", "\\cite{", "{key}", ",", "}"),
+        YankFormat::new('p', "Pandoc citation", "[", "@{key}", "; ", "]"),
+        YankFormat::new('t', "Typst citation", "", "@{key}", " ", ""),
+        YankFormat::new('d', "DOI link", "", "https://doi.org/{doi}", "\n", ""),
+        YankFormat::new(
+            'f',
+            "formatted reference",
+            "",
+            concat!(
+                "{if author}{author:last|join(\", \", \" and \")}{else}{editor:last|join(\", \", \" and \")} (ed.){end}",
+                " {if year}({year}){else}(n.d.){end}. {title|decode}.",
+                "{if journal} {journal|decode}{if volume}, {volume}{if number}({number}){end}{end}",
+                "{if pages}, {pages|decode}{end}.{end}",
+                "{if doi} https://doi.org/{doi}{end}",
+            ),
+            "\n",
+            "",
+        ),
+    ]
+}
+
+/// Reads yank formats from a config file, which has a section for every format, like
+///
+/// ```text
+/// [yank c]
+/// name = LaTeX citation
+/// prefix = \cite{
+/// template = {key}
+/// separator = ","
+/// suffix = }
+/// ```
+///
+/// Values can be quoted to keep spaces around them, and then `\n` is a newline. Templates are described at
+/// [`Template`]. The formats replace the default formats with the same key, and the others are added.
+pub fn parse_formats(config: &str, defaults: Vec<YankFormat>) -> Result<Vec<YankFormat>, String> {
+    let mut formats = defaults;
+    let mut current: Option<usize> = None;
+    for (number, line) in config.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| Err(format!("Line {}: {}", number + 1, message));
+
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = None;
+            let mut words = section.split_whitespace();
+            if words.next() != Some("yank") {
+                // Other sections are for other settings
+                continue;
+            }
+            let key = match (words.next(), words.next()) {
+                (Some(key), None) if key.chars().count() == 1 => key.chars().next().unwrap_or(' '),
+                _ => return error("a yank format should be named by one key, like [yank c]."),
+            };
+            let position = formats.iter().position(|format| format.key == key);
+            current = Some(position.unwrap_or_else(|| {
+                formats.push(YankFormat::new(
+                    key,
+                    &key.to_string(),
+                    "",
+                    "{key}",
+                    ", ",
+                    "",
+                ));
+                formats.len() - 1
+            }));
+            continue;
+        }
+
+        let Some((name, value)) = line.split_once('=') else {
+            return error("expected a setting, like template = {key}.");
+        };
+        let Some(format) = current.map(|i| &mut formats[i]) else {
+            continue;
+        };
+        let value = unquote(value.trim());
+        match name.trim() {
+            "name" => format.name = value,
+            "prefix" => format.prefix = value,
+            "template" => match Template::parse(&value) {
+                Ok(template) => format.template = template,
+                Err(message) => return error(&format!("the template is wrong: {}", message)),
+            },
+            "separator" => format.separator = value,
+            "suffix" => format.suffix = value,
+            other => return error(&format!("unknown setting {}.", other)),
+        }
+    }
+    Ok(formats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parse::parse_bibtex;
+
+    fn references() -> Vec<Reference> {
+        let bibtex = "
+            @article{Veerman2021, author = {Veerman, Ana and M{\\\"u}ller, Jan}, year = 2021,
+                title = {On {C}itations}, journal = {J. Cit.}, volume = 3, pages = {1--10},
+                doi = {10.1000/182}}
+            @book{Doe2020, author = {Doe, John}, title = {A Book}}
+        ";
+        parse_bibtex(bibtex.to_string(), "test.bib").references
+    }
+
+    fn format(key: char) -> YankFormat {
+        default_formats()
+            .into_iter()
+            .find(|format| format.key == key)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let references = references();
+        let both: Vec<&Reference> = references.iter().collect();
+        assert_eq!(
+            Ok(String::from("\\cite{Veerman2021,Doe2020}")),
+            format('c').apply(&both)
+        );
+        assert_eq!(
+            Ok(String::from("[@Veerman2021; @Doe2020]")),
+            format('p').apply(&both)
+        );
+        assert_eq!(
+            Ok(String::from("@Veerman2021 @Doe2020")),
+            format('t').apply(&both)
+        );
+        assert_eq!(
+            Ok(String::from(
+                "Veerman and Müller (2021). On Citations. J. Cit., 3, 1–10. https://doi.org/10.1000/182\nDoe (n.d.). A Book."
+            )),
+            format('f').apply(&both)
+        );
+        assert_eq!(
+            Err(String::from("Doe2020 has no doi.")),
+            format('d').apply(&both)
+        );
+        assert!(format('y')
+            .apply(&both)
+            .unwrap()
+            .starts_with("@article{Veerman2021,"));
+    }
+
+    #[test]
+    fn test_parse_formats() {
+        let config = "
+            # Overrides the citation format, and adds one
+            [yank c]
+            prefix = \\citep{
+
+            [yank m]
+            name = Markdown link
+            template = [{title}](https://doi.org/{doi})
+            separator = \"\\n\"
+
+            [other]
+            anything = goes
+        ";
+        let formats = parse_formats(config, default_formats()).unwrap();
+        assert_eq!(default_formats().len() + 1, formats.len());
+        let cite = formats.iter().find(|format| format.key == 'c').unwrap();
+        assert_eq!(
+            ("\\citep{", "}"),
+            (cite.prefix.as_str(), cite.suffix.as_str())
+        );
+        let markdown = formats.last().unwrap();
+        assert_eq!(
+            YankFormat::new(
+                'm',
+                "Markdown link",
+                "",
+                "[{title}](https://doi.org/{doi})",
+                "\n",
+                ""
+            ),
+            *markdown
+        );
+
+        assert_eq!(
+            Err(String::from(
+                "Line 1: a yank format should be named by one key, like [yank c]."
+            )),
+            parse_formats("[yank cite]", default_formats())
+        );
+        assert_eq!(
+            Err(String::from(
+                "Line 2: the template is wrong: there is no filter shout."
+            )),
+            parse_formats("[yank c]\ntemplate = {key|shout}", default_formats())
+        );
+        assert_eq!(
+            Err(String::from("Line 2: unknown setting color.")),
+            parse_formats("[yank c]\ncolor = red", default_formats())
+        );
+    }
+}